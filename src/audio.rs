@@ -1,30 +1,92 @@
-use std::{collections::VecDeque, error::Error, io::Cursor, sync::mpsc::Sender};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    io::Cursor,
+    sync::mpsc::Sender,
+};
 
 use kira::{
     clock::{ClockHandle, ClockSpeed, ClockTime},
-    manager::{backend::DefaultBackend, AudioManager, AudioManagerSettings},
-    sound::static_sound::{StaticSoundData, StaticSoundSettings},
+    manager::{
+        backend::{mock::MockBackendSettings, Backend},
+        AudioManager, AudioManagerSettings,
+    },
+    sound::static_sound::{StaticSoundData, StaticSoundHandle, StaticSoundSettings},
+    track::{TrackBuilder, TrackHandle, TrackId},
     tween::Tween,
+    Frame,
 };
 
 use macroquad::prelude::*;
 
 use crate::{
+    backing_track::BackingTrack,
     config::AppConfig,
-    consts::{TxMsg, UserHit, ALL_INSTRUMENTS, BEATS_PER_LOOP, TICK_SCHEDULE_AHEAD},
-    voices::{Instrument, Voices},
+    consts::{
+        TxMsg, UserHit, ALL_INSTRUMENTS, DEFAULT_BEATS_PER_LOOP, DEFAULT_SUBDIVISION,
+        SCHEDULE_AHEAD_BEATS, TICKS_PER_BEAT,
+    },
+    headless_backend::HeadlessBackend,
+    kit::{Kit, KitSample},
+    metronome::{CountIn, GapClick, Metronome, MetronomeSound, Pulse, ALL_METRONOME_SOUNDS},
+    mixer::Mixer,
+    output_backend::{OutputBackend, OutputSettings},
+    practice_region::PracticeRegion,
+    score::MISS_MARGIN,
+    voices::{Dynamic, Instrument, TimeSignature, Voices},
 };
 
 /// Audio is the audio player and tracks the user's hits in relation to the audio timing.
 ///
 /// These two responsibilities co-exist so that the audio player's subtle timing issues
 /// can be measured and corrected for.
-pub struct Audio {
-    manager: AudioManager<DefaultBackend>,
+///
+/// Positions in the loop are measured in "groove" beats, which exclude count-ins.
+/// The clock keeps ticking through count-ins, so it runs ahead of the groove by `clock_offset_beats`.
+///
+/// Audio plays through an `AudioBackend`. The app uses an `OutputBackend` (the chosen sound card, or silence), while tests use
+/// a `HeadlessBackend` (see `Audio::new_headless`), which needs no sound card and only moves the clock when advanced.
+pub struct Audio<B: AudioBackend = OutputBackend> {
+    manager: AudioManager<B>,
+    /// the clock ticks `TICKS_PER_BEAT` times per beat, independent of the grid
     clock: ClockHandle,
-    last_scheduled_tick: f64,
+    clock_offset_beats: f64,
+    /// the groove beat that the latest count-in leads into
+    count_in_end_beat: f64,
+    count_in: CountIn,
+    last_scheduled_beat: f64,
+    /// sounds that have been handed to the audio manager, along with the clock beat they play at
+    scheduled_sounds: Vec<(f64, StaticSoundHandle)>,
+    /// clicks of the latest count-in, which aren't affected by rescheduling
+    count_in_sounds: Vec<StaticSoundHandle>,
     bpm: f64,
+    beats_per_loop: f64,
+    /// when set, only this part of the loop is repeated. Loop positions and scoring are then relative to its start.
+    practice_region: Option<PracticeRegion>,
+    time_signature: TimeSignature,
+    /// grid steps per beat of the current loop. This doesn't affect timing, since notes are scheduled by beat.
+    subdivision: u32,
     metronome_enabled: bool,
+    metronome: Metronome,
+    gap_click: GapClick,
+    kit: Kit,
+    /// decoded samples, keyed by file path. Cloning these is cheap, since the audio frames are shared.
+    samples: HashMap<String, StaticSoundData>,
+    mixer: Mixer,
+    /// each instrument and the metronome plays through its own track, so the mixer can set its volume
+    instrument_tracks: HashMap<Instrument, TrackHandle>,
+    metronome_track: TrackHandle,
+    backing_track: Option<BackingTrack>,
+    /// the decoded backing track, which is played as a single sound rather than scheduled note by note
+    backing_track_sound: Option<StaticSoundData>,
+    backing_track_handle: Option<StaticSoundHandle>,
+    backing_track_track: TrackHandle,
+    hit_sounds_enabled: bool,
+    hit_track: TrackHandle,
+    /// the output device and buffer size, as saved in the config. Changes take effect after a restart.
+    output_settings: OutputSettings,
+    /// no sound card could be opened, so the loop plays without sound
+    silent: bool,
 
     pub user_hits: Vec<UserHit>,
     calibration_input: VecDeque<f64>,
@@ -32,48 +94,189 @@ pub struct Audio {
 
     tx: Sender<TxMsg>,
 
-    // debug only
-    last_beat: i32,
+    /// the loop that was playing when last checked, to notice when a new one starts
+    last_loop: i32,
 }
 
 const DEFAULT_BPM: f64 = 60.;
 const MIN_BPM: f64 = 40.;
 const MAX_BPM: f64 = 240.;
 
+/// AudioBackend is a kira backend that `Audio` can play through.
+/// Real backends render on their own thread, while the headless backend only renders when advanced.
+pub trait AudioBackend: Backend + Sized {
+    /// renders the given amount of audio, for backends that don't render by themselves
+    fn advance(manager: &mut AudioManager<Self>, seconds: f64);
+}
+
+impl AudioBackend for OutputBackend {
+    fn advance(manager: &mut AudioManager<Self>, seconds: f64) {
+        // without a sound card, time still has to pass for the loop to play
+        if let OutputBackend::Silent(backend) = manager.backend_mut() {
+            backend.render(seconds);
+        }
+    }
+}
+
+impl AudioBackend for HeadlessBackend {
+    fn advance(manager: &mut AudioManager<Self>, seconds: f64) {
+        manager.backend_mut().render(seconds);
+    }
+}
+
 impl Audio {
     pub fn new(conf: &AppConfig, tx: Sender<TxMsg>) -> Self {
-        let mut manager =
-            AudioManager::<DefaultBackend>::new(AudioManagerSettings::default()).unwrap();
+        let manager = AudioManager::<OutputBackend>::new(AudioManagerSettings {
+            backend_settings: OutputSettings {
+                device_name: conf.output_device.clone(),
+                buffer_size_frames: conf.buffer_size_frames,
+            },
+            ..AudioManagerSettings::default()
+        })
+        .unwrap();
+        let mut audio = Audio::new_with_manager(manager, conf, tx);
+        audio.silent = audio.manager.backend_mut().is_silent();
+        audio
+    }
+
+    pub fn new_mock(conf: &AppConfig, tx: Sender<TxMsg>) -> Self {
+        let mut audio = Audio::new(conf, tx);
+        audio.user_hits = vec![UserHit {
+            instrument: Instrument::ClosedHihat,
+            clock_tick: 1.0,
+        }];
+        audio
+    }
+}
+
+impl Audio<HeadlessBackend> {
+    /// Audio that isn't played to a sound card. Time only passes when calling `advance` or `render`.
+    pub fn new_headless(sample_rate: u32, conf: &AppConfig, tx: Sender<TxMsg>) -> Self {
+        let manager = AudioManager::<HeadlessBackend>::new(AudioManagerSettings {
+            backend_settings: MockBackendSettings { sample_rate },
+            ..AudioManagerSettings::default()
+        })
+        .unwrap();
+        Audio::new_with_manager(manager, conf, tx)
+    }
+
+    /// like `advance`, but returns the rendered audio
+    pub fn render(&mut self, seconds: f64) -> Vec<Frame> {
+        self.manager.backend_mut().render(seconds)
+    }
+
+    /// renders repetitions of the loop from the start, faster than real time
+    pub fn render_loops(
+        &mut self,
+        voices: &Voices,
+        repetitions: u32,
+    ) -> Result<Vec<Frame>, Box<dyn Error>> {
+        let sample_rate = self.manager.backend_mut().sample_rate();
+        let total_seconds =
+            self.get_loop_length() * repetitions as f64 * self.get_seconds_per_beat();
+        let num_frames = (total_seconds * sample_rate as f64).round() as usize;
+        // render in blocks shorter than the schedule-ahead window, so each note is scheduled before it's due
+        let block_seconds = SCHEDULE_AHEAD_BEATS / 4. * self.get_seconds_per_beat();
+
+        self.toggle_pause()?;
+        let mut frames = Vec::with_capacity(num_frames);
+        while frames.len() < num_frames {
+            self.schedule(voices)?;
+            frames.extend(self.render(block_seconds));
+        }
+        frames.truncate(num_frames);
+        Ok(frames)
+    }
+}
+
+impl<B: AudioBackend> Audio<B> {
+    fn new_with_manager(mut manager: AudioManager<B>, conf: &AppConfig, tx: Sender<TxMsg>) -> Self {
         let clock = manager
-            // TODO: investigate bpm * 2 stuff
-            .add_clock(ClockSpeed::TicksPerMinute(DEFAULT_BPM * 2. as f64))
+            .add_clock(ClockSpeed::TicksPerMinute(
+                DEFAULT_BPM * TICKS_PER_BEAT as f64,
+            ))
             .unwrap();
+        let instrument_tracks = ALL_INSTRUMENTS
+            .iter()
+            .map(|ins| (*ins, manager.add_sub_track(TrackBuilder::new()).unwrap()))
+            .collect();
+        let metronome_track = manager.add_sub_track(TrackBuilder::new()).unwrap();
+        let backing_track_track = manager.add_sub_track(TrackBuilder::new()).unwrap();
+        let hit_track = manager.add_sub_track(TrackBuilder::new()).unwrap();
 
         tx.send(TxMsg::AudioNew).unwrap();
 
         Self {
             manager,
             clock,
-            last_scheduled_tick: -1.,
+            clock_offset_beats: 0.,
+            count_in_end_beat: 0.,
+            count_in: CountIn::default(),
+            last_scheduled_beat: -1.,
+            scheduled_sounds: vec![],
+            count_in_sounds: vec![],
             bpm: DEFAULT_BPM,
+            beats_per_loop: DEFAULT_BEATS_PER_LOOP,
+            practice_region: None,
+            time_signature: TimeSignature::default(),
+            subdivision: DEFAULT_SUBDIVISION,
             metronome_enabled: false,
+            metronome: Metronome::default(),
+            gap_click: GapClick::default(),
+            kit: Kit::default(),
+            samples: HashMap::new(),
+            mixer: Mixer::new(),
+            instrument_tracks,
+            metronome_track,
+            backing_track: None,
+            backing_track_sound: None,
+            backing_track_handle: None,
+            backing_track_track,
+            hit_sounds_enabled: conf.hit_sounds_enabled,
+            hit_track,
+            output_settings: OutputSettings {
+                device_name: conf.output_device.clone(),
+                buffer_size_frames: conf.buffer_size_frames,
+            },
+            silent: false,
 
             user_hits: vec![],
             calibration_input: VecDeque::new(),
             configured_audio_latency_seconds: conf.audio_latency_seconds,
-            last_beat: -1,
+            last_loop: -1,
 
             tx,
         }
     }
 
-    pub fn new_mock(conf: &AppConfig, tx: Sender<TxMsg>) -> Self {
-        let mut audio = Audio::new(conf, tx);
-        audio.user_hits = vec![UserHit {
-            instrument: Instrument::ClosedHihat,
-            clock_tick: 1.0,
-        }];
-        audio
+    /// a silent copy of this audio, for rendering the loop faster than real time, e.g. on another thread.
+    /// it sounds as it would when played live: with the same kit, mixer, tempo and metronome, but without a count-in.
+    /// loop messages are sent to `tx`, so its receiver has to be kept while rendering.
+    pub fn offline_copy(&self, sample_rate: u32, tx: Sender<TxMsg>) -> Audio<HeadlessBackend> {
+        let mut offline = Audio::new_headless(sample_rate, &AppConfig::default(), tx);
+        offline.kit = self.kit.clone();
+        offline.samples = self.samples.clone();
+        offline.mixer = self.mixer.clone();
+        offline.apply_mixer();
+        offline.metronome_enabled = self.metronome_enabled;
+        offline.metronome = self.metronome;
+        offline.gap_click = self.gap_click;
+        offline.beats_per_loop = self.beats_per_loop;
+        offline.practice_region = self.practice_region;
+        offline.time_signature = self.time_signature;
+        offline.count_in = CountIn {
+            length: 0,
+            ..self.count_in
+        };
+        offline.set_bpm(self.bpm);
+        // tempo changes are tweened, so let it settle before the clock starts
+        offline.advance(0.1);
+        offline
+    }
+
+    /// moves time forward on backends that don't render by themselves. Does nothing when playing to a sound card.
+    pub fn advance(&mut self, seconds: f64) {
+        B::advance(&mut self.manager, seconds);
     }
 
     // audio latency
@@ -85,71 +288,313 @@ impl Audio {
         self.configured_audio_latency_seconds = latency;
     }
 
+    /// true when no sound card could be opened, so nothing is heard
+    pub fn is_silent(&self) -> bool {
+        self.silent
+    }
+
+    pub fn get_output_settings(&self) -> &OutputSettings {
+        &self.output_settings
+    }
+
+    /// the output settings can't be changed while playing, so they're used the next time the app starts
+    pub fn set_output_settings(&mut self, output_settings: OutputSettings) {
+        self.output_settings = output_settings;
+    }
+
+    pub fn get_kit_name(&self) -> &str {
+        &self.kit.name
+    }
+
+    /// switches to a new drum kit, decoding all of its samples.
+    /// returns an error listing any samples that couldn't be loaded; the rest are still usable.
+    pub async fn set_kit(&mut self, kit: Kit) -> Result<(), Box<dyn Error>> {
+        self.kit = kit;
+        self.reschedule();
+        self.preload_samples().await
+    }
+
+    pub fn get_backing_track(&self) -> Option<&BackingTrack> {
+        self.backing_track.as_ref()
+    }
+
+    /// switches to a new backing track (or none), decoding it and starting it in time with the groove if playing
+    pub async fn set_backing_track(
+        &mut self,
+        backing_track: Option<BackingTrack>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.stop_backing_track();
+        self.backing_track_sound = None;
+        self.backing_track = None;
+        if let Some(backing_track) = &backing_track {
+            self.backing_track_sound = Some(load_sample(&backing_track.file).await?);
+        }
+        self.backing_track = backing_track;
+        self.restart_backing_track()
+    }
+
+    /// a negative offset is for a recording with a lead-in, so it starts after the groove does
+    pub fn set_backing_track_offset(&mut self, offset_seconds: f64) -> Result<(), Box<dyn Error>> {
+        if let Some(backing_track) = &mut self.backing_track {
+            backing_track.offset_seconds = offset_seconds;
+        }
+        self.restart_backing_track()
+    }
+
+    pub fn toggle_backing_track_loop(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(backing_track) = &mut self.backing_track {
+            backing_track.loop_region = !backing_track.loop_region;
+        }
+        self.restart_backing_track()
+    }
+
+    /// plays the backing track from the position that lines up with the given groove beat,
+    /// starting when the clock reaches that beat
+    fn start_backing_track(&mut self, groove_beat: f64) -> Result<(), Box<dyn Error>> {
+        self.stop_backing_track();
+        let (Some(backing_track), Some(sound)) = (&self.backing_track, &self.backing_track_sound)
+        else {
+            return Ok(());
+        };
+
+        // a practice region always repeats its section of the recording, so it stays in time.
+        // a section that starts before the recording does (during its lead-in) can't be repeated
+        // without cutting the lead-in short, so that plays on through instead.
+        let (region_start, region_end) = backing_track.region_seconds(
+            self.loop_start(),
+            self.loop_start() + self.get_loop_length(),
+        );
+        let repeat =
+            (backing_track.loop_region || self.practice_region.is_some()) && region_start >= 0.;
+        let beat = if repeat {
+            self.loop_start() + groove_beat.rem_euclid(self.get_loop_length())
+        } else {
+            groove_beat
+        };
+        let lead_in_beats = backing_track.beats_until_start(beat);
+        let mut sound_settings = StaticSoundSettings::new()
+            .start_position(backing_track.position_seconds(beat).max(0.))
+            .playback_rate(backing_track.playback_rate(self.bpm))
+            .output_destination(&self.backing_track_track);
+        if repeat {
+            sound_settings = sound_settings.loop_region(region_start..region_end);
+        }
+        let handle = schedule_note(
+            groove_beat + lead_in_beats + self.clock_offset_beats,
+            &self.clock,
+            &mut self.manager,
+            sound,
+            sound_settings,
+        )?;
+        self.backing_track_handle = Some(handle);
+        Ok(())
+    }
+
+    /// restarts the backing track in time with the groove, e.g. after the tempo changes.
+    /// it picks up from the next step of the loop's subdivision, so it doesn't start late.
+    fn restart_backing_track(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.is_paused() {
+            return Ok(());
+        }
+        let groove_beat = if self.is_counting_in() {
+            self.count_in_end_beat
+        } else {
+            let steps_per_beat = self.subdivision.max(1) as f64;
+            ((self.current_groove_beat() * steps_per_beat).floor() + 1.) / steps_per_beat
+        };
+        self.start_backing_track(groove_beat)
+    }
+
+    fn stop_backing_track(&mut self) {
+        if let Some(mut handle) = self.backing_track_handle.take() {
+            handle.stop(Tween::default());
+        }
+    }
+
+    /// decodes every sample that may be played, so that scheduling a note doesn't hit the disk.
+    async fn preload_samples(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut sound_paths: Vec<String> = ALL_METRONOME_SOUNDS
+            .iter()
+            .map(|sound| sound.get_audio_file().to_string())
+            .collect();
+        let mut failures = vec![];
+        for ins in ALL_INSTRUMENTS.iter() {
+            match self.kit.get_sample(ins) {
+                Some(sample) => sound_paths.push(sample.file.clone()),
+                None => failures.push(format!("{:?} (not in kit '{}')", ins, self.kit.name)),
+            }
+        }
+
+        self.samples.clear();
+        for sound_path in sound_paths {
+            match load_sample(&sound_path).await {
+                Ok(sound) => {
+                    self.samples.insert(sound_path, sound);
+                }
+                Err(e) => failures.push(format!("{} ({})", sound_path, e)),
+            }
+        }
+
+        if !failures.is_empty() {
+            return Err(format!("unable to load samples: {}", failures.join(", ")).into());
+        }
+        Ok(())
+    }
+
     // TODO: Move this outside and then use it to summary loop accuracy
     fn check_if_new_beat_or_new_loop(self: &mut Self) {
-        // For debugging, print when we pass an integer beat
-        let current_beat = self.current_beat() as i32;
-        if current_beat != self.last_beat {
-            // log::debug!("Beat: {}", current_beat as i32);
-            self.last_beat = current_beat as i32;
-            // if new loop, print that too
-            if current_beat == 0 {
-                self.tx
-                    .send(TxMsg::StartingLoop(self.current_loop()))
-                    .unwrap();
-                // log::debug!("Starting loop num #{:?}", self.current_loop());
-            }
+        if self.is_counting_in() {
+            return;
+        }
+
+        // loops are counted rather than checking for beat 0, since a practice region may start mid-beat
+        let current_loop = self.current_loop();
+        if current_loop != self.last_loop {
+            self.last_loop = current_loop;
+            self.tx.send(TxMsg::StartingLoop(current_loop)).unwrap();
+            // log::debug!("Starting loop num #{:?}", current_loop);
         }
     }
 
     /// schedule should be run within each game tick to schedule the audio
-    pub async fn schedule(self: &mut Self, voices: &Voices) -> Result<(), Box<dyn Error>> {
+    pub fn schedule(self: &mut Self, voices: &Voices) -> Result<(), Box<dyn Error>> {
         self.check_if_new_beat_or_new_loop();
 
-        let current = self.current_clock_tick();
-        if current <= self.last_scheduled_tick {
+        // forget sounds that have already played. A sound right on the clock may not have, e.g. while paused at the start.
+        let current_clock_beat = self.current_clock_beat();
+        self.scheduled_sounds
+            .retain(|(beat, _)| *beat >= current_clock_beat);
+
+        let beat_to_schedule = self.current_groove_beat() + SCHEDULE_AHEAD_BEATS;
+        if beat_to_schedule <= self.last_scheduled_beat {
             return Ok(());
         }
 
-        let tick_to_schedule = current + TICK_SCHEDULE_AHEAD;
-
         log::debug!(
             "Scheduling from {} to {}",
-            self.last_scheduled_tick,
-            tick_to_schedule
+            self.last_scheduled_beat,
+            beat_to_schedule
         );
 
         for ins in ALL_INSTRUMENTS.iter() {
-            let notes = voices.get_instrument_beats(ins);
-            let sound_path = Voices::get_audio_file_for_instrument(ins);
-            schedule_audio(
-                notes,
-                sound_path,
-                &mut self.manager,
-                &self.clock,
-                self.last_scheduled_tick,
-                tick_to_schedule,
-            )
-            .await?;
+            let Some(sample) = self.kit.get_sample(ins).cloned() else {
+                continue;
+            };
+            let notes = self.notes_in_loop(&voices.get_instrument_notes(ins));
+            let track = self.instrument_tracks[ins].id();
+            let gapped = self.gap_click.silence_voices;
+            self.schedule_audio(&notes, &sample, track, beat_to_schedule, gapped)?;
         }
 
         if self.is_metronome_enabled() {
-            // TODO: play a different sound at start of each measure
-            // clicks on quarter notes
-            let metronome_notes = vec![0., 2., 4., 6., 8., 10., 12., 14.];
-            let sound_path = "res/sounds/click.wav"; // TODO: metronome.ogg?
-            schedule_audio(
-                &metronome_notes,
-                sound_path,
-                &mut self.manager,
+            let metronome_notes = self.notes_in_loop(
+                &self
+                    .metronome
+                    .clicks(&self.time_signature, self.beats_per_loop),
+            );
+            let sample = KitSample::new(self.metronome.sound.get_audio_file());
+            let track = self.metronome_track.id();
+            self.schedule_audio(&metronome_notes, &sample, track, beat_to_schedule, true)?;
+        }
+
+        self.last_scheduled_beat = beat_to_schedule;
+
+        Ok(())
+    }
+
+    /// schedules notes for a single sound to be played between last_scheduled_beat and beat_to_schedule.
+    /// gapped sounds are skipped during the silent bars of the gap click.
+    fn schedule_audio(
+        &mut self,
+        notes: &[(f64, Dynamic)],
+        sample: &KitSample,
+        track: TrackId,
+        beat_to_schedule: f64,
+        gapped: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        // missing samples were reported when preloading
+        let Some(sound) = self.samples.get(&sample.file) else {
+            return Ok(());
+        };
+
+        let beats = beats_to_schedule(
+            notes,
+            self.last_scheduled_beat,
+            beat_to_schedule,
+            self.get_loop_length(),
+        );
+        for (beat, dynamic) in beats {
+            if gapped && !self.gap_click.is_audible(beat, &self.time_signature) {
+                continue;
+            }
+            let sound_settings = StaticSoundSettings::new()
+                .volume(sample.gain * dynamic.gain())
+                .panning(sample.panning())
+                .output_destination(track);
+            let clock_beat = beat + self.clock_offset_beats;
+            let handle = schedule_note(
+                clock_beat,
                 &self.clock,
-                self.last_scheduled_tick,
-                tick_to_schedule,
-            )
-            .await?;
+                &mut self.manager,
+                sound,
+                sound_settings,
+            )?;
+            self.scheduled_sounds.push((clock_beat, handle));
         }
 
-        self.last_scheduled_tick = tick_to_schedule;
+        Ok(())
+    }
+
+    /// cancels sounds that haven't played yet, so that the next `schedule` picks up
+    /// any changes to the voices, tempo or grid straight away
+    pub fn reschedule(&mut self) {
+        // the app keeps scheduling while paused, so there can be sounds waiting even then
+        self.cancel_scheduled_sounds();
+        self.last_scheduled_beat = if self.is_counting_in() {
+            // reschedule from where the groove resumes, including a note right on that beat
+            self.count_in_end_beat - f64::EPSILON
+        } else {
+            self.current_groove_beat()
+        };
+    }
+
+    /// stops sounds that were scheduled but haven't played yet
+    fn cancel_scheduled_sounds(&mut self) {
+        let current_clock_beat = self.current_clock_beat();
+        for (beat, handle) in self.scheduled_sounds.iter_mut() {
+            if *beat >= current_clock_beat {
+                handle.stop(Tween::default());
+            }
+        }
+        self.scheduled_sounds.clear();
+    }
+
+    /// delays the groove by the count-in, and schedules the count-in's clicks
+    fn start_count_in(&mut self) -> Result<(), Box<dyn Error>> {
+        // resuming during a count-in starts it over, still leading into the same beat
+        self.count_in_end_beat = self.current_groove_beat().max(self.count_in_end_beat);
+        let count_in_start = self.current_clock_beat();
+        self.count_in_sounds.clear();
+        self.clock_offset_beats = count_in_start + self.count_in.length_beats(&self.time_signature)
+            - self.count_in_end_beat;
+
+        let sample = KitSample::new(self.metronome.sound.get_audio_file());
+        let Some(sound) = self.samples.get(&sample.file) else {
+            return Ok(());
+        };
+        for (beat, dynamic) in self.count_in.clicks(&self.time_signature) {
+            let sound_settings = StaticSoundSettings::new()
+                .volume(sample.gain * dynamic.gain())
+                .output_destination(&self.metronome_track);
+            let handle = schedule_note(
+                count_in_start + beat,
+                &self.clock,
+                &mut self.manager,
+                sound,
+                sound_settings,
+            )?;
+            self.count_in_sounds.push(handle);
+        }
 
         Ok(())
     }
@@ -158,16 +603,129 @@ impl Audio {
         self.clock.time().ticks as f64 + self.clock.time().fraction
     }
 
+    /// beats elapsed since the clock started
+    fn current_clock_beat(&self) -> f64 {
+        self.current_clock_tick() / TICKS_PER_BEAT as f64
+    }
+
+    /// beats elapsed since the groove started, excluding count-ins. negative during the first count-in.
+    fn current_groove_beat(&self) -> f64 {
+        self.current_clock_beat() - self.clock_offset_beats
+    }
+
+    /// position in the groove, which holds still during a count-in
+    fn current_loop_position(&self) -> f64 {
+        self.current_groove_beat().max(self.count_in_end_beat)
+    }
+
+    /// position in the arrangement, which stays within the practice region if there is one
     pub fn current_beat(self: &Self) -> f64 {
-        self.current_clock_tick() % BEATS_PER_LOOP
+        self.loop_start() + self.current_loop_position() % self.get_loop_length()
     }
 
     pub fn current_loop(self: &Self) -> i32 {
-        (self.current_clock_tick() / BEATS_PER_LOOP) as i32
+        (self.current_loop_position() / self.get_loop_length()) as i32
+    }
+
+    pub fn is_counting_in(&self) -> bool {
+        self.current_groove_beat() < self.count_in_end_beat
+    }
+
+    /// beats of the meter left in the count-in, if counting in
+    pub fn count_in_remaining(&self) -> Option<u32> {
+        if !self.is_counting_in() {
+            return None;
+        }
+        let remaining_beats = self.count_in_end_beat - self.current_groove_beat();
+        Some((remaining_beats / self.time_signature.pulse_beats()).ceil() as u32)
+    }
+
+    pub fn get_count_in(&self) -> CountIn {
+        self.count_in
+    }
+
+    pub fn set_count_in(&mut self, count_in: CountIn) {
+        self.count_in = count_in;
+    }
+
+    pub fn get_beats_per_loop(&self) -> f64 {
+        self.beats_per_loop
+    }
+
+    pub fn set_beats_per_loop(&mut self, beats_per_loop: f64) -> Result<(), Box<dyn Error>> {
+        self.beats_per_loop = beats_per_loop;
+        // the region belonged to the previous loop
+        self.practice_region = None;
+        self.reschedule();
+        self.restart_backing_track()
+    }
+
+    pub fn get_practice_region(&self) -> Option<PracticeRegion> {
+        self.practice_region
+    }
+
+    /// repeats only part of the loop, or the whole loop when `None`
+    pub fn set_practice_region(
+        &mut self,
+        practice_region: Option<PracticeRegion>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.practice_region = practice_region
+            .filter(|region| region.length_beats() > 0. && region.end_beat <= self.beats_per_loop);
+        // while playing, the loop count jumps when the loop length changes, which isn't a new loop
+        if !self.is_paused() {
+            self.last_loop = self.current_loop();
+        }
+        self.reschedule();
+        self.restart_backing_track()
+    }
+
+    /// the length of what's being repeated: the practice region, or else the whole loop
+    pub fn get_loop_length(&self) -> f64 {
+        self.practice_region
+            .map_or(self.beats_per_loop, |region| region.length_beats())
+    }
+
+    /// the beat of the arrangement where each repetition starts
+    fn loop_start(&self) -> f64 {
+        self.practice_region.map_or(0., |region| region.start_beat)
+    }
+
+    /// the voices that are being repeated, with the practice region (if any) starting at beat 0.
+    /// User hits are scored against these.
+    pub fn voices_in_loop(&self, voices: &Voices) -> Voices {
+        match &self.practice_region {
+            Some(region) => region.crop_voices(voices),
+            None => voices.clone(),
+        }
+    }
+
+    /// like `voices_in_loop`, for a single sound's notes
+    fn notes_in_loop(&self, notes: &[(f64, Dynamic)]) -> Vec<(f64, Dynamic)> {
+        match &self.practice_region {
+            Some(region) => region.crop_notes(notes),
+            None => notes.to_vec(),
+        }
+    }
+
+    pub fn get_time_signature(&self) -> TimeSignature {
+        self.time_signature
+    }
+
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) {
+        self.time_signature = time_signature;
+        self.reschedule();
+    }
+
+    pub fn get_subdivision(&self) -> u32 {
+        self.subdivision
+    }
+
+    pub fn set_subdivision(&mut self, subdivision: u32) {
+        self.subdivision = subdivision;
     }
 
-    fn get_seconds_per_tick(self: &Self) -> f64 {
-        60. / self.bpm / 2.
+    fn get_seconds_per_beat(&self) -> f64 {
+        60. / self.bpm
     }
 
     pub fn get_bpm(self: &Self) -> f64 {
@@ -176,16 +734,43 @@ impl Audio {
 
     pub fn set_bpm(self: &mut Self, bpm: f64) {
         self.bpm = clamp(bpm, MIN_BPM, MAX_BPM);
-        self.clock
-            .set_speed(ClockSpeed::TicksPerMinute(bpm * 2.), Tween::default())
+        self.update_clock_speed();
+        self.reschedule();
+        if let Some(handle) = &mut self.backing_track_handle {
+            if let Some(backing_track) = &self.backing_track {
+                handle.set_playback_rate(backing_track.playback_rate(self.bpm), Tween::default());
+            }
+        }
     }
 
-    pub fn toggle_pause(self: &mut Self) {
+    fn update_clock_speed(&mut self) {
+        self.clock.set_speed(
+            ClockSpeed::TicksPerMinute(self.bpm * TICKS_PER_BEAT as f64),
+            Tween::default(),
+        )
+    }
+
+    /// pauses playback, or resumes it after a count-in
+    pub fn toggle_pause(self: &mut Self) -> Result<(), Box<dyn Error>> {
         if self.clock.ticking() {
             self.clock.pause();
+            // sounds scheduled past this point would otherwise play during the next count-in
+            self.cancel_scheduled_sounds();
+            for handle in self.count_in_sounds.iter_mut() {
+                handle.stop(Tween::default());
+            }
+            self.last_scheduled_beat = self.current_groove_beat();
+            self.stop_backing_track();
         } else {
+            // sounds scheduled while paused don't allow for the count-in, so they're replaced
+            self.cancel_scheduled_sounds();
+            self.start_count_in()?;
+            // schedule from where the groove resumes, including a note right on that beat
+            self.last_scheduled_beat = self.count_in_end_beat - f64::EPSILON;
+            self.start_backing_track(self.count_in_end_beat)?;
             self.clock.start();
         }
+        Ok(())
     }
 
     pub fn is_paused(self: &Self) -> bool {
@@ -194,29 +779,144 @@ impl Audio {
 
     pub fn toggle_metronome(self: &mut Self) {
         self.metronome_enabled = !self.metronome_enabled;
+        self.reschedule();
     }
 
     pub fn is_metronome_enabled(self: &Self) -> bool {
         self.metronome_enabled
     }
 
+    pub fn get_metronome(&self) -> Metronome {
+        self.metronome
+    }
+
+    pub fn set_metronome_pulse(&mut self, pulse: Pulse) {
+        self.metronome.pulse = pulse;
+        self.reschedule();
+    }
+
+    pub fn set_metronome_sound(&mut self, sound: MetronomeSound) {
+        self.metronome.sound = sound;
+        self.reschedule();
+    }
+
+    pub fn toggle_metronome_accent(&mut self) {
+        self.metronome.accent_downbeat = !self.metronome.accent_downbeat;
+        self.reschedule();
+    }
+
+    pub fn get_gap_click(&self) -> GapClick {
+        self.gap_click
+    }
+
+    pub fn set_gap_click(&mut self, gap_click: GapClick) {
+        self.gap_click = gap_click;
+        self.reschedule();
+    }
+
+    pub fn get_mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    pub fn set_instrument_volume(&mut self, ins: &Instrument, volume: f64) {
+        self.mixer.set_volume(ins, volume);
+        self.apply_mixer();
+    }
+
+    pub fn toggle_mute(&mut self, ins: &Instrument) {
+        self.mixer.toggle_mute(ins);
+        self.apply_mixer();
+    }
+
+    pub fn toggle_solo(&mut self, ins: &Instrument) {
+        self.mixer.toggle_solo(ins);
+        self.apply_mixer();
+    }
+
+    pub fn set_metronome_volume(&mut self, volume: f64) {
+        self.mixer.set_metronome_volume(volume);
+        self.apply_mixer();
+    }
+
+    pub fn set_backing_track_volume(&mut self, volume: f64) {
+        self.mixer.set_backing_track_volume(volume);
+        self.apply_mixer();
+    }
+
+    pub fn set_hit_volume(&mut self, volume: f64) {
+        self.mixer.set_hit_volume(volume);
+        self.apply_mixer();
+    }
+
+    pub fn set_master_volume(&mut self, volume: f64) {
+        self.mixer.set_master_volume(volume);
+        self.apply_mixer();
+    }
+
+    /// updates each track's volume to match the mixer. Applies to sounds that are already scheduled, too.
+    fn apply_mixer(&mut self) {
+        for (ins, track) in self.instrument_tracks.iter_mut() {
+            track.set_volume(self.mixer.effective_volume(ins), Tween::default());
+        }
+        self.metronome_track
+            .set_volume(self.mixer.metronome_volume, Tween::default());
+        self.backing_track_track
+            .set_volume(self.mixer.backing_track_volume, Tween::default());
+        self.hit_track
+            .set_volume(self.mixer.hit_volume, Tween::default());
+        self.manager
+            .main_track()
+            .set_volume(self.mixer.master_volume, Tween::default());
+    }
+
     // TODO: Feels like this could be moved elsewhere, with a quick lookup against audio if needed (e.g. get_seconds_per_tick)
 
+    pub fn is_hit_sounds_enabled(&self) -> bool {
+        self.hit_sounds_enabled
+    }
+
+    pub fn toggle_hit_sounds(&mut self) {
+        self.hit_sounds_enabled = !self.hit_sounds_enabled;
+    }
+
+    /// plays the instrument's sample straight away, if hit sounds are enabled.
+    /// This isn't scheduled on the clock, so it's heard as soon as possible after the hit.
+    pub fn play_hit_sound(&mut self, instrument: Instrument) -> Result<(), Box<dyn Error>> {
+        if !self.hit_sounds_enabled {
+            return Ok(());
+        }
+        let Some(sample) = self.kit.get_sample(&instrument) else {
+            return Ok(());
+        };
+        let Some(sound) = self.samples.get(&sample.file) else {
+            return Ok(());
+        };
+        let sound_settings = StaticSoundSettings::new()
+            .volume(sample.gain)
+            .panning(sample.panning())
+            .output_destination(&self.hit_track);
+        self.manager.play(sound.with_settings(sound_settings))?;
+        Ok(())
+    }
+
     /// saves a user's hits, so they can be displayed and checked for accuracy
     pub fn track_user_hit(self: &mut Self, instrument: Instrument, processing_delay_s: f64) {
-        // convert processing delay to ticks, based on BPM
-        let ticks_per_second = 1. / self.get_seconds_per_tick();
-        let processing_delay_ticks = ticks_per_second * processing_delay_s;
+        // convert processing delay to beats, based on BPM
+        let beats_per_second = 1. / self.get_seconds_per_beat();
+        let processing_delay_beats = beats_per_second * processing_delay_s;
+        let beat = self.current_groove_beat() - processing_delay_beats;
 
-        self.user_hits.push(UserHit::new(
-            instrument,
-            self.current_clock_tick() - processing_delay_ticks,
-        ));
+        // hits during the count-in aren't scored, unless they could be early hits for the first note
+        if beat < self.count_in_end_beat - MISS_MARGIN {
+            return;
+        }
+
+        self.user_hits.push(UserHit::new(instrument, beat));
 
         log::debug!(
             "Capture at beat = {}, clock = {}",
             self.current_beat(),
-            self.current_clock_tick()
+            self.current_clock_beat()
         );
     }
 
@@ -227,7 +927,7 @@ impl Audio {
         log::debug!(
             "Capture + calibrate at beat = {}, clock = {}",
             self.current_beat(),
-            self.current_clock_tick()
+            self.current_clock_beat()
         );
         // compute average distance from integer beats
         let dists = self.calibration_input.iter().map(|x| x - x.round());
@@ -245,75 +945,406 @@ impl Audio {
     }
 }
 
-/// schedules notes for a single sound to be played between last_scheduled_tick and tick_to_schedule
-async fn schedule_audio(
-    notes: &Vec<f64>,
-    sound_path: &str,
-    manager: &mut AudioManager,
-    clock: &ClockHandle,
-    last_scheduled_tick: f64,
-    tick_to_schedule: f64,
-) -> Result<(), Box<dyn Error>> {
-    let prev_beat = last_scheduled_tick % BEATS_PER_LOOP;
-    let next_beat = tick_to_schedule % BEATS_PER_LOOP;
-    let loop_num = (last_scheduled_tick / BEATS_PER_LOOP) as i32; // floor
-    for note in notes.iter() {
-        if note > &prev_beat && note <= &next_beat {
-            schedule_note(note, loop_num, clock, manager, sound_path).await?;
-        };
-
-        // handle wrap-around case
-        if next_beat < prev_beat {
-            // from prev_beat to end of loop
-            if *note > prev_beat && *note <= BEATS_PER_LOOP as f64 {
-                schedule_note(note, loop_num, clock, manager, sound_path).await?;
-            }
-            // from start of loop to next beat
-            if *note >= 0. && *note <= next_beat {
-                schedule_note(note, loop_num + 1, clock, manager, sound_path).await?;
+/// finds the notes of a single sound that fall after last_scheduled_beat, up to and including beat_to_schedule.
+/// the loop can repeat several times in between, e.g. for a practice region that's a beat or shorter.
+/// returns them as beats since the groove started, so they can be scheduled directly.
+fn beats_to_schedule(
+    notes: &[(f64, Dynamic)],
+    last_scheduled_beat: f64,
+    beat_to_schedule: f64,
+    beats_per_loop: f64,
+) -> Vec<(f64, Dynamic)> {
+    let mut out = vec![];
+    if beats_per_loop <= 0. {
+        return out;
+    }
+    // nothing plays before the groove starts
+    let first_loop = (last_scheduled_beat.max(0.) / beats_per_loop).floor() as i64;
+    let last_loop = (beat_to_schedule / beats_per_loop).floor() as i64;
+    for loop_num in first_loop..=last_loop {
+        let loop_start = loop_num as f64 * beats_per_loop;
+        for (note, dynamic) in notes.iter() {
+            let beat = note + loop_start;
+            if beat > last_scheduled_beat && beat <= beat_to_schedule {
+                out.push((beat, *dynamic));
             }
         }
     }
 
-    Ok(())
+    out
 }
 
-/// schedules a single note to be played at a specific tick
-async fn schedule_note(
-    note: &f64,
-    loop_num: i32,
-    clock: &ClockHandle,
-    manager: &mut AudioManager,
-    sound_path: &str,
-) -> Result<(), Box<dyn Error>> {
-    let note_tick = (*note + (loop_num as f64) * BEATS_PER_LOOP) as u64;
-    // log::debug!("\tScheduling {} ({}) at {}", sound_path, note, note_tick);
+/// reads and decodes a sample, so it's ready to be played
+async fn load_sample(sound_path: &str) -> Result<StaticSoundData, Box<dyn Error>> {
     let f = load_file(sound_path).await?;
-    let sound_settings = StaticSoundSettings::new()
-        .volume(get_volume(sound_path))
-        .start_time(ClockTime {
-            clock: clock.id(),
-            ticks: note_tick,
-            fraction: 0.,
+    Ok(StaticSoundData::from_cursor(Cursor::new(f))?)
+}
+
+/// schedules a single note to be played at a specific beat (since the clock started)
+fn schedule_note(
+    beat: f64,
+    clock: &ClockHandle,
+    manager: &mut AudioManager<impl Backend>,
+    sound: &StaticSoundData,
+    sound_settings: StaticSoundSettings,
+) -> Result<StaticSoundHandle, Box<dyn Error>> {
+    // notes may fall between ticks, e.g. triplets, or after resuming from a pause mid-tick
+    let note_tick = beat * TICKS_PER_BEAT as f64;
+    let sound_settings = sound_settings.start_time(ClockTime {
+        clock: clock.id(),
+        ticks: note_tick.floor() as u64,
+        fraction: note_tick.fract(),
+    });
+
+    Ok(manager.play(sound.with_settings(sound_settings))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+
+    use kira::{
+        sound::static_sound::{StaticSoundData, StaticSoundSettings},
+        Frame,
+    };
+
+    use crate::{
+        audio::Audio,
+        config::AppConfig,
+        consts::TxMsg,
+        headless_backend::HeadlessBackend,
+        metronome::{CountIn, MetronomeSound},
+        practice_region::PracticeRegion,
+        score::{compute_last_loop_summary, Accuracy},
+        voices::{Instrument, Voices},
+    };
+
+    /// each rendered sample moves the clock forward by 1ms
+    const HEADLESS_SAMPLE_RATE: u32 = 1000;
+
+    /// audio at 120 BPM (half a second per beat), with a snare sample and the metronome click
+    fn headless_audio() -> (Audio<HeadlessBackend>, Receiver<TxMsg>) {
+        let (tx, rx) = mpsc::channel();
+        let mut audio = Audio::new_headless(HEADLESS_SAMPLE_RATE, &AppConfig::default(), tx);
+        audio.kit =
+            serde_json::from_str(r#"{"samples": {"snare": {"file": "snare.wav"}}}"#).unwrap();
+        for file in ["snare.wav", MetronomeSound::Click.get_audio_file()] {
+            let silence = StaticSoundData {
+                sample_rate: HEADLESS_SAMPLE_RATE,
+                frames: vec![Frame::ZERO; 10].into(),
+                settings: StaticSoundSettings::default(),
+                slice: None,
+            };
+            audio.samples.insert(file.to_string(), silence);
+        }
+        audio.set_bpm(120.);
+        (audio, rx)
+    }
+
+    fn snare_on_each_beat() -> Voices {
+        let mut voices = Voices::new();
+        for beat in [0., 1., 2., 3.] {
+            voices.toggle_beat(Instrument::Snare, beat);
+        }
+        voices
+    }
+
+    /// groove beats of the sounds that are waiting to play
+    fn scheduled_beats(audio: &Audio<HeadlessBackend>) -> Vec<f64> {
+        audio
+            .scheduled_sounds
+            .iter()
+            .map(|(beat, _)| beat - audio.clock_offset_beats)
+            .collect()
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "expected {} to be near {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn it_schedules_notes_ahead_of_the_clock() {
+        let (mut audio, _rx) = headless_audio();
+        audio.set_count_in(CountIn {
+            length: 0,
+            ..CountIn::default()
         });
+        let voices = snare_on_each_beat();
+
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![0., 1.]);
+
+        audio.advance(0.75);
+        assert_near(audio.current_beat(), 1.5);
+        audio.schedule(&voices).unwrap();
+        // sounds that have played are forgotten
+        assert_eq!(scheduled_beats(&audio), vec![2.]);
 
-    let sound = StaticSoundData::from_cursor(Cursor::new(f));
-    if let Ok(sound) = sound {
-        manager.play(sound.with_settings(sound_settings))?;
+        // pausing cancels sounds that haven't played yet
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+        assert!(audio.is_paused());
+        assert!(scheduled_beats(&audio).is_empty());
     }
 
-    Ok(())
-}
+    #[test]
+    fn it_repeats_only_the_practice_region() {
+        let (mut audio, rx) = headless_audio();
+        audio.set_count_in(CountIn {
+            length: 0,
+            ..CountIn::default()
+        });
+        audio
+            .set_practice_region(Some(PracticeRegion {
+                start_beat: 2.,
+                end_beat: 3.5,
+            }))
+            .unwrap();
+        assert_eq!(audio.get_loop_length(), 1.5);
+        let voices = snare_on_each_beat();
+
+        // the snares on beats 2 and 3 are played, measured from the start of the region
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![0., 1.]);
+        assert_near(audio.current_beat(), 2.);
+
+        audio.advance(0.6);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![1.5]);
+
+        // after the region, it starts over from the region's start
+        audio.advance(0.3);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(audio.current_loop(), 1);
+        assert_near(audio.current_beat(), 2.3);
+        let loops: Vec<_> = rx
+            .try_iter()
+            .filter_map(|msg| match msg {
+                TxMsg::StartingLoop(loop_num) => Some(loop_num),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(loops, vec![0, 1]);
+    }
+
+    #[test]
+    fn it_repeats_a_practice_region_shorter_than_the_schedule() {
+        let (mut audio, _rx) = headless_audio();
+        audio.set_count_in(CountIn {
+            length: 0,
+            ..CountIn::default()
+        });
+        let voices = snare_on_each_beat();
+
+        // a region as long as the beats scheduled ahead: the snare on beat 2 repeats every beat
+        audio
+            .set_practice_region(Some(PracticeRegion {
+                start_beat: 2.,
+                end_beat: 3.,
+            }))
+            .unwrap();
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![0., 1.]);
+
+        // rescheduling, e.g. after a tempo change, picks up the next repetition
+        audio.advance(0.6);
+        audio.reschedule();
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![2.]);
 
-fn get_volume(sound_path: &str) -> f64 {
-    match sound_path {
-        "res/sounds/open-hihat.wav" => 0.5,
-        "res/sounds/ride.wav" => 0.15,
-        "res/sounds/crash.wav" => 0.4,
-        "res/sounds/tom-hi.wav" => 0.25,
-        "res/sounds/tom-med.wav" => 0.25,
-        "res/sounds/tom-low.wav" => 0.25,
-        "res/sounds/pedal-hihat.wav" => 0.5,
-        _ => 1.0,
+        // a region shorter than that repeats more than once in between
+        audio
+            .set_practice_region(Some(PracticeRegion {
+                start_beat: 2.,
+                end_beat: 2.5,
+            }))
+            .unwrap();
+        assert_near(audio.current_groove_beat(), 1.2);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![1.5, 2.]);
+    }
+
+    #[test]
+    fn it_scores_hits_against_the_clock() {
+        let (mut audio, _rx) = headless_audio();
+        audio.set_count_in(CountIn {
+            length: 0,
+            ..CountIn::default()
+        });
+        let voices = snare_on_each_beat();
+
+        audio.toggle_pause().unwrap();
+        audio.advance(0.5);
+        audio.track_user_hit(Instrument::Snare, 0.);
+        // a hit that took 50ms to reach the game lands back on beat 2
+        audio.advance(0.55);
+        audio.track_user_hit(Instrument::Snare, 0.05);
+
+        assert_eq!(audio.user_hits.len(), 2);
+        assert_near(audio.user_hits[0].clock_tick, 1.);
+        assert_near(audio.user_hits[1].clock_tick, 2.);
+
+        let summary =
+            compute_last_loop_summary(&audio.user_hits, &voices, 0., audio.get_beats_per_loop());
+        assert_eq!(
+            summary.get_score_tracker(&Instrument::Snare).accuracies,
+            vec![
+                Accuracy::Miss,
+                Accuracy::Correct,
+                Accuracy::Correct,
+                Accuracy::Miss
+            ],
+        );
+    }
+
+    #[test]
+    fn it_delays_the_groove_by_the_count_in() {
+        let (mut audio, _rx) = headless_audio();
+        let voices = snare_on_each_beat();
+
+        // the default count-in is a bar of 4/4
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+        assert!(audio.is_counting_in());
+        assert_eq!(audio.count_in_remaining(), Some(4));
+        assert_eq!(audio.count_in_sounds.len(), 4);
+
+        audio.schedule(&voices).unwrap();
+        assert!(scheduled_beats(&audio).is_empty());
+        // hits during the count-in aren't scored
+        audio.track_user_hit(Instrument::Snare, 0.);
+        assert!(audio.user_hits.is_empty());
+
+        audio.advance(1.75);
+        assert_eq!(audio.current_beat(), 0.);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![0.]);
+
+        audio.advance(0.3);
+        assert!(!audio.is_counting_in());
+        assert_near(audio.current_beat(), 0.1);
+    }
+
+    #[test]
+    fn it_schedules_the_first_beat_after_the_count_in() {
+        let (mut audio, _rx) = headless_audio();
+        let voices = snare_on_each_beat();
+
+        // the app schedules every frame, including before playback starts
+        audio.schedule(&voices).unwrap();
+
+        audio.toggle_pause().unwrap();
+        audio.advance(1.75);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![0.]);
+
+        audio.advance(0.3);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![1.]);
+    }
+
+    #[test]
+    fn it_reschedules_changes_made_while_paused() {
+        let (mut audio, _rx) = headless_audio();
+        audio.set_count_in(CountIn {
+            length: 0,
+            ..CountIn::default()
+        });
+        let mut voices = snare_on_each_beat();
+        audio.toggle_pause().unwrap();
+        audio.advance(0.6);
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+
+        // the app keeps scheduling while paused
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![2.]);
+
+        // moving a note cancels its sound straight away
+        voices.toggle_beat(Instrument::Snare, 2.);
+        voices.toggle_beat(Instrument::Snare, 1.5);
+        audio.reschedule();
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![1.5]);
+
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![1.5]);
+    }
+
+    #[test]
+    fn it_restarts_a_count_in_that_was_paused() {
+        let (mut audio, _rx) = headless_audio();
+        let voices = snare_on_each_beat();
+
+        // pause halfway through the count-in
+        audio.toggle_pause().unwrap();
+        audio.advance(1.1);
+        assert_eq!(audio.count_in_remaining(), Some(2));
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+
+        // resuming counts in a whole bar again, into the start of the groove
+        audio.toggle_pause().unwrap();
+        audio.advance(0.);
+        assert_eq!(audio.count_in_remaining(), Some(4));
+        audio.advance(1.75);
+        assert_eq!(audio.current_beat(), 0.);
+        audio.schedule(&voices).unwrap();
+        assert_eq!(scheduled_beats(&audio), vec![0.]);
+
+        audio.advance(0.3);
+        assert!(!audio.is_counting_in());
+        assert_near(audio.current_beat(), 0.1);
+    }
+
+    #[test]
+    fn it_renders_notes_on_the_beat() {
+        let (mut audio, _rx) = headless_audio();
+        let sample_rate = 8000;
+        let mut frames = vec![Frame::ZERO; 10];
+        frames[0] = Frame::from_mono(1.);
+        let click = StaticSoundData {
+            sample_rate,
+            frames: frames.into(),
+            settings: StaticSoundSettings::default(),
+            slice: None,
+        };
+        audio.samples.insert("snare.wav".to_string(), click);
+
+        let (tx, _rx) = mpsc::channel();
+        let frames = audio
+            .offline_copy(sample_rate, tx)
+            .render_loops(&snare_on_each_beat(), 2)
+            .unwrap();
+        // 2 loops of 4 beats, at half a second per beat
+        assert_eq!(frames.len(), 4 * sample_rate as usize);
+
+        // notes may start a frame early, so the first note of the next loop can sneak into the last frame
+        let onsets: Vec<usize> = (0..frames.len() - 2)
+            .filter(|i| frames[*i].left != 0. && (*i == 0 || frames[i - 1].left == 0.))
+            .collect();
+        assert_eq!(onsets.len(), 8);
+        for (beat, onset) in onsets.iter().enumerate() {
+            let expected = beat * sample_rate as usize / 2;
+            assert!(
+                onset.abs_diff(expected) <= 2,
+                "beat {} at frame {}",
+                beat,
+                onset
+            );
+        }
     }
 }
@@ -0,0 +1,78 @@
+/*
+  Backing tracks, which are recordings played in time with the loop.
+*/
+use serde::{Deserialize, Serialize};
+
+/// BackingTrack is a recording to play along with, e.g. the song section a loop was transcribed from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackingTrack {
+    /// path to the audio file (WAV, OGG or MP3)
+    pub file: String,
+    /// seconds into the file where the first beat of the loop is
+    #[serde(default)]
+    pub offset_seconds: f64,
+    /// tempo of the recording. It's sped up or slowed down to follow the loop's tempo.
+    pub bpm: f64,
+    /// repeat the section of the recording that lines up with the loop, instead of playing on through the song
+    #[serde(default)]
+    pub loop_region: bool,
+}
+
+impl BackingTrack {
+    /// position in the file, in seconds, that lines up with the given beat of the loop
+    pub fn position_seconds(&self, beat: f64) -> f64 {
+        self.offset_seconds + beat * self.seconds_per_beat()
+    }
+
+    /// the section of the file that lines up with the given beats of the loop, as (start, end) in seconds
+    pub fn region_seconds(&self, start_beat: f64, end_beat: f64) -> (f64, f64) {
+        (
+            self.position_seconds(start_beat),
+            self.position_seconds(end_beat),
+        )
+    }
+
+    /// beats from the given beat of the loop until the recording starts, when it has a lead-in (a negative offset)
+    pub fn beats_until_start(&self, beat: f64) -> f64 {
+        (-self.position_seconds(beat)).max(0.) / self.seconds_per_beat()
+    }
+
+    /// how fast to play the file so it lines up with the given tempo
+    pub fn playback_rate(&self, bpm: f64) -> f64 {
+        bpm / self.bpm
+    }
+
+    fn seconds_per_beat(&self) -> f64 {
+        60. / self.bpm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backing_track::BackingTrack;
+
+    #[test]
+    fn it_lines_up_the_recording_with_the_groove() {
+        let track = BackingTrack {
+            file: "song.ogg".to_string(),
+            offset_seconds: 2.,
+            bpm: 120.,
+            loop_region: false,
+        };
+        assert_eq!(track.position_seconds(0.), 2.);
+        assert_eq!(track.position_seconds(6.), 5.);
+        assert_eq!(track.region_seconds(0., 4.), (2., 4.));
+        assert_eq!(track.region_seconds(2., 3.), (3., 3.5));
+        assert_eq!(track.beats_until_start(0.), 0.);
+
+        let track = BackingTrack {
+            offset_seconds: -1.,
+            ..track
+        };
+        assert_eq!(track.position_seconds(4.), 1.);
+        assert_eq!(track.beats_until_start(0.), 2.);
+        assert_eq!(track.beats_until_start(1.5), 0.5);
+        assert_eq!(track.beats_until_start(3.), 0.);
+        assert_eq!(track.playback_rate(90.), 0.75);
+    }
+}
@@ -1,8 +1,21 @@
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
     pub audio_latency_seconds: f64,
+    /// name of the selected drum kit. Empty means the first available kit.
+    pub kit_name: String,
+    /// play the instrument's sample when the user hits
+    pub hit_sounds_enabled: bool,
+    /// name of the audio output device. Empty means the system's default device.
+    pub output_device: String,
+    /// frames buffered by the audio output. 0 means the device's default.
+    pub buffer_size_frames: u32,
+    /// names of the MIDI input ports to play from, without ALSA's numbers. None means every port.
+    pub midi_ports: Option<Vec<String>>,
 }
 
 impl AppConfig {
@@ -14,6 +27,12 @@ impl AppConfig {
         }
     }
 
+    /// where the user's own MIDI mapping profiles are kept, next to the config file
+    pub fn user_midi_profiles_dir() -> Option<PathBuf> {
+        let config_file = confy::get_configuration_file_path("macroix", "AppConfig").ok()?;
+        Some(config_file.parent()?.join("midi_profiles"))
+    }
+
     pub fn save(&self) {
         match confy::store("macroix", "AppConfig", self) {
             // ignore failures. these happen in web builds
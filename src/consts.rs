@@ -7,12 +7,17 @@ use crate::voices::Instrument;
 pub const WINDOW_WIDTH: i32 = 1280;
 pub const WINDOW_HEIGHT: i32 = 720;
 
-pub const BEATS_PER_LOOP: f64 = 16.;
+// a beat is a quarter note
+pub const DEFAULT_BEATS_PER_LOOP: f64 = 4.;
+pub const DEFAULT_SUBDIVISION: u32 = 4; // grid steps per beat, i.e. 16th notes
+/// clock resolution, in ticks per beat (PPQ). Notes between ticks are scheduled using the clock's fractional time.
+pub const TICKS_PER_BEAT: u32 = 96;
+pub const MAX_GRID_SUBDIVISION: u32 = 16; // finest grid the UI will split a beat into, to show off-grid notes. Finer cells are too small to click.
 
 //
 // Audio
 //
-pub const TICK_SCHEDULE_AHEAD: f64 = 2.; // schedule audio this many (N) ticks ahead of time (i.e. N seconds ahead if at 60bpm)
+pub const SCHEDULE_AHEAD_BEATS: f64 = 1.; // schedule audio this many (N) beats ahead of time (i.e. N seconds ahead if at 60bpm)
 
 // General use
 pub const ALL_INSTRUMENTS: [Instrument; 10] = [
@@ -29,7 +34,14 @@ pub const ALL_INSTRUMENTS: [Instrument; 10] = [
 ];
 
 pub const GRID_ROWS: usize = ALL_INSTRUMENTS.len();
-pub const GRID_COLS: usize = BEATS_PER_LOOP as usize;
+
+pub const KITS_DIR: &str = "res/kits/";
+
+/// bundled MIDI mapping profiles. Users can add their own in `AppConfig::user_midi_profiles_dir`.
+pub const MIDI_PROFILES_DIR: &str = "res/midi_profiles/";
+
+/// where loops rendered to audio files are saved
+pub const RENDERS_DIR: &str = "renders/";
 
 // Message passing (TODO: move to events?)
 
@@ -43,6 +55,7 @@ pub enum TxMsg {
 #[derive(Debug, Clone)]
 pub struct UserHit {
     pub instrument: Instrument,
+    /// position in the groove when the hit happened, in beats since playback started (excluding count-ins)
     pub clock_tick: f64,
 }
 
@@ -54,7 +67,7 @@ impl UserHit {
         }
     }
 
-    pub fn beat(self: &Self) -> f64 {
-        self.clock_tick % BEATS_PER_LOOP
+    pub fn beat(self: &Self, beats_per_loop: f64) -> f64 {
+        self.clock_tick % beats_per_loop
     }
 }
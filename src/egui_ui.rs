@@ -1,5 +1,7 @@
 // mod app;
 
+use std::ops::RangeInclusive;
+
 use egui::{
     self,
     emath::{self, RectTransform},
@@ -13,15 +15,24 @@ use log::info;
 use macroquad::color::{GREEN, LIGHTGRAY, ORANGE, PURPLE, RED};
 
 use crate::{
-    consts::{UserHit, ALL_INSTRUMENTS, BEATS_PER_LOOP, GRID_COLS, GRID_ROWS},
+    backing_track::BackingTrack,
+    consts::{UserHit, ALL_INSTRUMENTS, DEFAULT_BEATS_PER_LOOP, DEFAULT_SUBDIVISION, GRID_ROWS},
     events::Events,
+    game::{MidiLearn, MidiPorts},
+    metronome::{
+        CountIn, GapClick, Metronome, ALL_COUNT_IN_UNITS, ALL_METRONOME_SOUNDS, ALL_PULSES,
+    },
+    mixer::Mixer,
+    output_backend::{OutputDevices, OutputSettings},
+    practice_region::PracticeRegion,
     score::{
-        compute_accuracy_of_single_hit, compute_last_loop_summary,
+        compute_accuracy_of_single_hit, compute_drift, compute_last_loop_summary,
         compute_loop_performance_for_voice, get_user_hit_timings_by_instrument, Accuracy,
         MISS_MARGIN,
     },
+    speed_trainer::{SpeedTrainer, SpeedTrainerSettings},
     ui::get_hits_from_nth_loop,
-    voices::{Instrument, Voices},
+    voices::{Dynamic, Instrument, TimeSignature, Voices},
 };
 
 // This resource holds information about the game:
@@ -29,21 +40,48 @@ pub struct UIState {
     selector_vec: Vec<String>,
     selected_idx: usize,
 
+    kit_names: Vec<String>,
+    selected_kit_name: String,
+
+    output_devices: Vec<String>,
+    output_settings: OutputSettings,
+    /// frames the chosen output device can buffer, or None if it doesn't say
+    output_buffer_sizes: Option<RangeInclusive<u32>>,
+    is_audio_silent: bool,
+
+    midi_learn: Option<MidiLearn>,
+    midi_ports: MidiPorts,
+
     is_playing: bool,
     bpm: f32,
     is_metronome_enabled: bool,
-    volume_metronome: f32,
-    volume_target_notes: f32,
+    is_hit_sounds_enabled: bool,
+    metronome: Metronome,
+    gap_click: GapClick,
+    count_in: CountIn,
+    count_in_remaining: Option<u32>,
+    mixer: Mixer,
+    backing_track: Option<BackingTrack>,
+    speed_trainer: SpeedTrainer,
 
     // audio
     current_loop: usize, // nth loop
     current_beat: f32,
+    beats_per_loop: f64,
+    practice_region: Option<PracticeRegion>,
+    /// the length of what's being repeated, which is shorter than the loop when practicing a region
+    loop_length: f64,
+    time_signature: TimeSignature,
+    subdivision: u32,
+    grid_subdivision: u32,
 
-    enabled_beats: [[bool; GRID_COLS]; GRID_ROWS],
+    /// the note in each grid cell, with its exact beat
+    enabled_beats: Vec<Vec<Option<(f64, Dynamic)>>>,
 
     latency_offset_s: f32,
 
     user_hits: Vec<UserHit>,
+    /// the notes that are scored, measured from the start of the practice region (if any)
     desired_hits: Voices,
 
     is_help_visible: bool,
@@ -67,18 +105,41 @@ impl Default for UIState {
             ],
             selected_idx: 0,
 
+            kit_names: vec![],
+            selected_kit_name: String::new(),
+
+            output_devices: vec![],
+            output_settings: OutputSettings::default(),
+            output_buffer_sizes: None,
+            is_audio_silent: false,
+
+            midi_learn: None,
+            midi_ports: MidiPorts::default(),
+
             current_loop: 2,
             current_beat: 2.3,
+            beats_per_loop: DEFAULT_BEATS_PER_LOOP,
+            practice_region: None,
+            loop_length: DEFAULT_BEATS_PER_LOOP,
+            time_signature: TimeSignature::default(),
+            subdivision: DEFAULT_SUBDIVISION,
+            grid_subdivision: DEFAULT_SUBDIVISION,
 
             bpm: 120.,
 
             is_metronome_enabled: false,
-            volume_metronome: 0.75,
-            volume_target_notes: 0.75,
+            is_hit_sounds_enabled: false,
+            metronome: Metronome::default(),
+            gap_click: GapClick::default(),
+            count_in: CountIn::default(),
+            count_in_remaining: None,
+            mixer: Mixer::new(),
+            backing_track: None,
+            speed_trainer: SpeedTrainer::default(),
 
             latency_offset_s: 0.,
 
-            enabled_beats: [[false; GRID_COLS]; GRID_ROWS],
+            enabled_beats: vec![vec![]; GRID_ROWS],
 
             user_hits: vec![],
             desired_hits: Voices::new(),
@@ -103,6 +164,39 @@ impl UIState {
         self.selected_idx = idx;
     }
 
+    pub fn set_kit_names(&mut self, kit_names: Vec<String>) {
+        self.kit_names = kit_names;
+    }
+
+    pub fn set_selected_kit_name(&mut self, name: &str) {
+        self.selected_kit_name = name.to_string();
+    }
+
+    pub fn set_metronome(&mut self, metronome: Metronome) {
+        self.metronome = metronome;
+    }
+
+    pub fn set_gap_click(&mut self, gap_click: GapClick) {
+        self.gap_click = gap_click;
+    }
+
+    pub fn set_count_in(&mut self, count_in: CountIn, remaining: Option<u32>) {
+        self.count_in = count_in;
+        self.count_in_remaining = remaining;
+    }
+
+    pub fn set_mixer(&mut self, mixer: &Mixer) {
+        self.mixer = mixer.clone();
+    }
+
+    pub fn set_backing_track(&mut self, backing_track: Option<&BackingTrack>) {
+        self.backing_track = backing_track.cloned();
+    }
+
+    pub fn set_speed_trainer(&mut self, speed_trainer: SpeedTrainer) {
+        self.speed_trainer = speed_trainer;
+    }
+
     pub fn set_is_playing(&mut self, is_playing: bool) {
         self.is_playing = is_playing;
     }
@@ -115,8 +209,36 @@ impl UIState {
         self.current_loop = val;
     }
 
-    pub fn set_enabled_beats(&mut self, voices: &Voices) {
-        self.enabled_beats = voices.to_enabled_beats();
+    pub fn set_beats_per_loop(&mut self, val: f64) {
+        self.beats_per_loop = val;
+    }
+
+    pub fn set_time_signature(&mut self, val: TimeSignature) {
+        self.time_signature = val;
+    }
+
+    pub fn set_subdivision(&mut self, val: u32) {
+        self.subdivision = val;
+    }
+
+    pub fn set_enabled_beats(&mut self, voices: &Voices, beats_per_loop: f64, subdivision: u32) {
+        self.grid_subdivision = voices.grid_subdivision(subdivision);
+        self.enabled_beats = voices.to_enabled_beats(beats_per_loop, self.grid_subdivision);
+    }
+
+    /// the beat grid has one column per cell, where each beat is split into `grid_subdivision` cells.
+    /// This is finer than the loop's subdivision when it has off-grid notes.
+    fn num_grid_cols(&self) -> usize {
+        (self.beats_per_loop * self.grid_subdivision as f64).ceil() as usize
+    }
+
+    /// how many grid cells make up one step of the loop's subdivision
+    fn cells_per_step(&self) -> usize {
+        (self.grid_subdivision / self.subdivision.max(1)).max(1) as usize
+    }
+
+    fn grid_width_scale(&self) -> f32 {
+        VIRTUAL_WIDTH / self.num_grid_cols() as f32
     }
 
     pub fn set_bpm(&mut self, bpm: f32) {
@@ -131,6 +253,20 @@ impl UIState {
         self.user_hits = hits.clone();
     }
 
+    pub fn set_practice_region(
+        &mut self,
+        practice_region: Option<PracticeRegion>,
+        loop_length: f64,
+    ) {
+        self.practice_region = practice_region;
+        self.loop_length = loop_length;
+    }
+
+    /// the beat of the arrangement where each repetition starts
+    fn loop_start(&self) -> f64 {
+        self.practice_region.map_or(0., |region| region.start_beat)
+    }
+
     pub fn set_desired_hits(&mut self, voices: &Voices) {
         self.desired_hits = voices.clone();
     }
@@ -139,6 +275,30 @@ impl UIState {
         self.is_metronome_enabled = enabled;
     }
 
+    pub fn set_hit_sounds_enabled(&mut self, enabled: bool) {
+        self.is_hit_sounds_enabled = enabled;
+    }
+
+    pub fn set_midi_learn(&mut self, midi_learn: Option<MidiLearn>) {
+        self.midi_learn = midi_learn;
+    }
+
+    pub fn set_midi_ports(&mut self, midi_ports: &MidiPorts) {
+        self.midi_ports = midi_ports.clone();
+    }
+
+    pub fn set_output(
+        &mut self,
+        devices: &OutputDevices,
+        output_settings: &OutputSettings,
+        is_silent: bool,
+    ) {
+        self.output_devices = devices.names();
+        self.output_buffer_sizes = devices.buffer_sizes(&output_settings.device_name);
+        self.output_settings = output_settings.clone();
+        self.is_audio_silent = is_silent;
+    }
+
     pub fn get_audio_latency_in_beats(&self) -> f32 {
         let beats_per_second = self.bpm / 60.;
         self.latency_offset_s * beats_per_second
@@ -196,6 +356,10 @@ fn help_window(ctx: &egui::Context, ui_state: &UIState) {
             ui.label("Show FPS");
             ui.label("z");
         });
+        ui.horizontal(|ui| {
+            ui.label("Change note dynamic (normal, accent, ghost)");
+            ui.label("right click");
+        });
     });
 }
 
@@ -243,6 +407,19 @@ fn draw_top_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Even
             let is_web = cfg!(target_arch = "wasm32");
             if !is_web {
                 ui.menu_button("File", |ui| {
+                    ui.horizontal(|ui| {
+                        // how many times to repeat the loop is only needed by this menu, so egui keeps it
+                        let id = ui.make_persistent_id("export_wav_repetitions");
+                        let mut repetitions = ui.data_mut(|d| *d.get_temp_mut_or(id, 4u32));
+                        if ui.button("Export WAV").clicked() {
+                            events.push(Events::ExportWav { repetitions });
+                        }
+                        egui::DragValue::new(&mut repetitions)
+                            .range(1..=64)
+                            .suffix(" loops")
+                            .ui(ui);
+                        ui.data_mut(|d| d.insert_temp(id, repetitions));
+                    });
                     if ui.button("Quit").clicked() {
                         events.push(Events::Quit);
                     }
@@ -271,7 +448,7 @@ fn draw_top_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Even
 
             ui.add(
                 // egui::ProgressBar::new(game_state.progress)
-                egui::ProgressBar::new(ui_state.current_beat / BEATS_PER_LOOP as f32)
+                egui::ProgressBar::new(ui_state.current_beat / ui_state.beats_per_loop as f32)
                     // .fill(Color32::BROWN)
                     .show_percentage(),
             );
@@ -300,6 +477,15 @@ fn draw_left_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Eve
             ui.separator();
 
             ui.add(egui::Label::new("**Volume**"));
+            ui.add(egui::Label::new("Master"));
+            let mut master_volume = ui_state.mixer.master_volume;
+            if ui
+                .add(egui::Slider::new(&mut master_volume, 0.0..=1.0))
+                .changed()
+            {
+                events.push(Events::SetMasterVolume(master_volume));
+            }
+
             ui.add(egui::Label::new("Metronome"));
             let button_text = match ui_state.is_metronome_enabled {
                 true => "Disable Metronome",
@@ -308,15 +494,39 @@ fn draw_left_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Eve
             if ui.button(button_text).clicked() {
                 events.push(Events::ToggleMetronome);
             }
+            let mut metronome_volume = ui_state.mixer.metronome_volume;
+            if ui
+                .add(egui::Slider::new(&mut metronome_volume, 0.0..=1.0))
+                .changed()
+            {
+                events.push(Events::SetMetronomeVolume(metronome_volume));
+            }
+            draw_metronome_settings(ui, ui_state, events);
+            draw_gap_click(ui, ui_state, events);
+            draw_backing_track(ui, ui_state, events);
+
+            let mut hit_sounds_enabled = ui_state.is_hit_sounds_enabled;
+            if ui
+                .checkbox(&mut hit_sounds_enabled, "Play sounds on hit")
+                .changed()
+            {
+                events.push(Events::ToggleHitSounds);
+            }
+            if ui_state.is_hit_sounds_enabled {
+                let mut hit_volume = ui_state.mixer.hit_volume;
+                if ui
+                    .add(egui::Slider::new(&mut hit_volume, 0.0..=1.0))
+                    .changed()
+                {
+                    events.push(Events::SetHitVolume(hit_volume));
+                }
+            }
 
-            // TODO: control volume: Metronome, target nomtes
-            // ui.add(egui::Slider::new(&mut ui_state.volume_metronome, 0.0..=1.0));
-            ui.add(egui::Label::new("Target Notes"));
-            // TODO
-            // ui.add(egui::Slider::new(
-            //     &mut ui_state.volume_target_notes,
-            //     0.0..=1.0,
-            // ));
+            CollapsingHeader::new("Target Notes")
+                .default_open(false)
+                .show(ui, |ui| {
+                    draw_mixer(ui, ui_state, events);
+                });
 
             ui.separator();
 
@@ -328,10 +538,335 @@ fn draw_left_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Eve
 
             ui.separator();
 
+            draw_practice_region(ui, ui_state, events);
+
+            ui.separator();
+
+            draw_speed_trainer(ui, ui_state, events);
+
+            ui.separator();
+
             gold_mode(ui, ui_state);
         });
 }
 
+fn draw_backing_track(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    ui.add(egui::Label::new("Backing Track"));
+    ui.horizontal(|ui| {
+        // the path being typed is only needed here, so egui keeps it
+        let id = ui.make_persistent_id("backing_track_file");
+        let mut file = ui.data_mut(|d| {
+            d.get_temp_mut_or_insert_with(id, || {
+                ui_state
+                    .backing_track
+                    .as_ref()
+                    .map(|backing_track| backing_track.file.clone())
+                    .unwrap_or_default()
+            })
+            .clone()
+        });
+        egui::TextEdit::singleline(&mut file)
+            .hint_text("path to a WAV or MP3 file")
+            .desired_width(160.)
+            .ui(ui);
+        if ui
+            .add_enabled(!file.is_empty(), egui::Button::new("Load"))
+            .on_hover_text("The recording is assumed to be at the loop's current tempo")
+            .clicked()
+        {
+            events.push(Events::SetBackingTrack(Some(BackingTrack {
+                file: file.clone(),
+                offset_seconds: 0.,
+                bpm: ui_state.bpm as f64,
+                loop_region: false,
+            })));
+        }
+        if ui
+            .add_enabled(
+                ui_state.backing_track.is_some(),
+                egui::Button::new("Remove"),
+            )
+            .clicked()
+        {
+            events.push(Events::SetBackingTrack(None));
+        }
+        ui.data_mut(|d| d.insert_temp(id, file));
+    });
+
+    let Some(backing_track) = &ui_state.backing_track else {
+        return;
+    };
+    ui.label(&backing_track.file);
+    let mut volume = ui_state.mixer.backing_track_volume;
+    if ui.add(egui::Slider::new(&mut volume, 0.0..=1.0)).changed() {
+        events.push(Events::SetBackingTrackVolume(volume));
+    }
+    ui.horizontal(|ui| {
+        ui.label("Offset (s)");
+        let mut offset_seconds = backing_track.offset_seconds;
+        if egui::DragValue::new(&mut offset_seconds)
+            .speed(0.005)
+            .range(-60.0..=60.0)
+            .fixed_decimals(3)
+            .ui(ui)
+            .changed()
+        {
+            events.push(Events::SetBackingTrackOffset(offset_seconds));
+        }
+    });
+    let mut loop_region = backing_track.loop_region;
+    if ui.checkbox(&mut loop_region, "Loop this section").changed() {
+        events.push(Events::ToggleBackingTrackLoop);
+    }
+}
+
+fn draw_gap_click(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    let mut gap_click = ui_state.gap_click;
+    let mut changed = ui.checkbox(&mut gap_click.enabled, "Gap click").changed();
+    if !gap_click.enabled {
+        if changed {
+            events.push(Events::SetGapClick(gap_click));
+        }
+        return;
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Play");
+        changed |= egui::DragValue::new(&mut gap_click.audible_bars)
+            .range(1..=16)
+            .ui(ui)
+            .changed();
+        ui.label("Mute");
+        changed |= egui::DragValue::new(&mut gap_click.silent_bars)
+            .range(0..=16)
+            .ui(ui)
+            .changed();
+        ui.label("bars");
+    });
+    changed |= ui
+        .checkbox(&mut gap_click.silence_voices, "Mute target notes too")
+        .changed();
+    if changed {
+        events.push(Events::SetGapClick(gap_click));
+    }
+
+    let drift = compute_drift(
+        &ui_state.user_hits,
+        &ui_state.desired_hits,
+        ui_state.get_audio_latency_in_beats() as f64,
+        ui_state.loop_length,
+        &gap_click,
+        &ui_state.time_signature,
+    );
+    let format_drift = |drift: Option<f64>| match drift {
+        Some(d) => format!("{:+.3} beats", d),
+        None => "-".to_string(),
+    };
+    ui.label(format!("Drift (audible): {}", format_drift(drift.audible)));
+    ui.label(format!("Drift (silent): {}", format_drift(drift.silent)));
+}
+
+fn draw_practice_region(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    ui.add(egui::Label::new("**Practice Region**"));
+
+    let subdivision = ui_state.subdivision;
+    let num_steps = (ui_state.beats_per_loop * subdivision as f64).ceil() as u32;
+    let mut enabled = ui_state.practice_region.is_some();
+    if ui.checkbox(&mut enabled, "Repeat steps").changed() {
+        let practice_region = if enabled {
+            Some(PracticeRegion::from_steps(
+                0,
+                num_steps.saturating_sub(1),
+                subdivision,
+            ))
+        } else {
+            None
+        };
+        events.push(Events::SetPracticeRegion(practice_region));
+    }
+
+    if let Some(region) = ui_state.practice_region {
+        // steps are shown counting from 1
+        let mut first_step = region.first_step(subdivision) + 1;
+        let mut last_step = region.last_step(subdivision) + 1;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("From");
+            changed |= egui::DragValue::new(&mut first_step)
+                .range(1..=last_step)
+                .ui(ui)
+                .changed();
+            ui.label("to");
+            changed |= egui::DragValue::new(&mut last_step)
+                .range(first_step..=num_steps)
+                .ui(ui)
+                .changed();
+        });
+        if changed {
+            events.push(Events::SetPracticeRegion(Some(PracticeRegion::from_steps(
+                first_step - 1,
+                last_step - 1,
+                subdivision,
+            ))));
+        }
+    }
+    ui.small("Shift-click two steps in the grid to select a region.");
+}
+
+fn draw_speed_trainer(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    ui.add(egui::Label::new("**Speed Trainer**"));
+
+    let trainer = ui_state.speed_trainer;
+    let mut enabled = trainer.enabled;
+    if ui.checkbox(&mut enabled, "Enabled").changed() {
+        events.push(Events::ToggleSpeedTrainer);
+    }
+
+    let mut settings = trainer.settings;
+    let mut changed = false;
+    egui::Grid::new("speed_trainer_settings").show(ui, |ui| {
+        ui.label("Start BPM");
+        changed |= egui::DragValue::new(&mut settings.start_bpm)
+            .range(40.0..=240.0)
+            .ui(ui)
+            .changed();
+        ui.end_row();
+
+        ui.label("Target BPM");
+        changed |= egui::DragValue::new(&mut settings.target_bpm)
+            .range(40.0..=240.0)
+            .ui(ui)
+            .changed();
+        ui.end_row();
+
+        ui.label("Step");
+        changed |= egui::DragValue::new(&mut settings.step_bpm)
+            .range(1.0..=20.0)
+            .ui(ui)
+            .changed();
+        ui.end_row();
+
+        ui.label("Loops per step");
+        changed |= egui::DragValue::new(&mut settings.loops_per_step)
+            .range(1..=32)
+            .ui(ui)
+            .changed();
+        ui.end_row();
+    });
+    changed |= ui
+        .checkbox(&mut settings.wrap_back, "Wrap back to start")
+        .changed();
+    if changed {
+        events.push(Events::SetSpeedTrainerSettings(settings));
+    }
+
+    if trainer.enabled {
+        let SpeedTrainerSettings {
+            target_bpm,
+            loops_per_step,
+            ..
+        } = settings;
+        ui.add(
+            egui::ProgressBar::new(trainer.progress(ui_state.bpm as f64) as f32).text(format!(
+                "{} / {} BPM (loop {}/{})",
+                ui_state.bpm,
+                target_bpm,
+                trainer.loops_at_current_step() + 1,
+                loops_per_step
+            )),
+        );
+    }
+}
+
+fn draw_metronome_settings(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    let metronome = ui_state.metronome;
+    egui::ComboBox::from_label("Pulse")
+        .selected_text(format!("{:?}", metronome.pulse))
+        .show_ui(ui, |ui| {
+            for pulse in ALL_PULSES.iter() {
+                if ui
+                    .selectable_label(metronome.pulse == *pulse, format!("{:?}", pulse))
+                    .clicked()
+                {
+                    events.push(Events::SetMetronomePulse(*pulse));
+                }
+            }
+        });
+    egui::ComboBox::from_label("Sound")
+        .selected_text(format!("{:?}", metronome.sound))
+        .show_ui(ui, |ui| {
+            for sound in ALL_METRONOME_SOUNDS.iter() {
+                if ui
+                    .selectable_label(metronome.sound == *sound, format!("{:?}", sound))
+                    .clicked()
+                {
+                    events.push(Events::SetMetronomeSound(*sound));
+                }
+            }
+        });
+    let mut accent_downbeat = metronome.accent_downbeat;
+    if ui
+        .checkbox(&mut accent_downbeat, "Accent downbeat")
+        .changed()
+    {
+        events.push(Events::ToggleMetronomeAccent);
+    }
+
+    let count_in = ui_state.count_in;
+    ui.horizontal(|ui| {
+        ui.label("Count-in");
+        let mut length = count_in.length;
+        if egui::DragValue::new(&mut length)
+            .range(0..=16)
+            .ui(ui)
+            .changed()
+        {
+            events.push(Events::SetCountIn(CountIn { length, ..count_in }));
+        }
+        egui::ComboBox::from_id_source("count_in_unit")
+            .selected_text(format!("{:?}", count_in.unit))
+            .show_ui(ui, |ui| {
+                for unit in ALL_COUNT_IN_UNITS.iter() {
+                    if ui
+                        .selectable_label(count_in.unit == *unit, format!("{:?}", unit))
+                        .clicked()
+                    {
+                        events.push(Events::SetCountIn(CountIn {
+                            unit: *unit,
+                            ..count_in
+                        }));
+                    }
+                }
+            });
+    });
+}
+
+/// a volume slider plus mute (M) and solo (S) toggles for each instrument
+fn draw_mixer(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    for ins in ALL_INSTRUMENTS.iter() {
+        let channel = ui_state.mixer.get_channel(ins);
+        ui.label(format!("{:?}", ins));
+        ui.horizontal(|ui| {
+            let mut volume = channel.volume;
+            if ui
+                .add(egui::Slider::new(&mut volume, 0.0..=1.0).show_value(false))
+                .changed()
+            {
+                events.push(Events::SetInstrumentVolume {
+                    instrument: *ins,
+                    volume,
+                });
+            }
+            if ui.selectable_label(channel.muted, "M").clicked() {
+                events.push(Events::ToggleMute(*ins));
+            }
+            if ui.selectable_label(channel.soloed, "S").clicked() {
+                events.push(Events::ToggleSolo(*ins));
+            }
+        });
+    }
+}
+
 fn draw_right_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Events>) {
     egui::SidePanel::right("right_panel")
         .resizable(true)
@@ -366,6 +901,29 @@ fn draw_right_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Ev
                     }
                 });
 
+            let kit_text = if ui_state.selected_kit_name.is_empty() {
+                "No kit"
+            } else {
+                &ui_state.selected_kit_name
+            };
+            egui::ComboBox::from_label("Choose Kit")
+                .selected_text(kit_text)
+                .show_ui(ui, |ui| {
+                    for (i, name) in ui_state.kit_names.iter().enumerate() {
+                        let is_selected = *name == ui_state.selected_kit_name;
+                        if ui.selectable_label(is_selected, name).clicked() {
+                            events.push(Events::ChangeKit(i));
+                        }
+                    }
+                });
+
+            ui.label(format!(
+                "{}/{} time, {} steps per beat",
+                ui_state.time_signature.numerator,
+                ui_state.time_signature.denominator,
+                ui_state.subdivision
+            ));
+
             ui.separator();
 
             ui.group(|ui| {
@@ -385,6 +943,15 @@ fn draw_right_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Ev
                 }
             });
 
+            ui.separator();
+            draw_audio_output(ui, ui_state, events);
+
+            ui.separator();
+            draw_midi_ports(ui, ui_state, events);
+
+            ui.separator();
+            draw_midi_learn(ui, ui_state, events);
+
             ui.separator();
             egui::widgets::global_dark_light_mode_buttons(ui);
 
@@ -402,6 +969,153 @@ fn draw_right_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Ev
         });
 }
 
+fn draw_audio_output(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    ui.group(|ui| {
+        ui.label("Audio Output");
+        if ui_state.is_audio_silent {
+            ui.colored_label(egui::Color32::RED, "No audio device. Playing silently.");
+        }
+
+        let settings = &ui_state.output_settings;
+        let device_text = if settings.device_name.is_empty() {
+            "Default"
+        } else {
+            &settings.device_name
+        };
+        egui::ComboBox::from_label("Device")
+            .selected_text(device_text)
+            .show_ui(ui, |ui| {
+                if ui
+                    .selectable_label(settings.device_name.is_empty(), "Default")
+                    .clicked()
+                {
+                    events.push(Events::SetOutputSettings(OutputSettings {
+                        device_name: String::new(),
+                        ..settings.clone()
+                    }));
+                }
+                for name in &ui_state.output_devices {
+                    let is_selected = *name == settings.device_name;
+                    if ui.selectable_label(is_selected, name).clicked() {
+                        events.push(Events::SetOutputSettings(OutputSettings {
+                            device_name: name.clone(),
+                            ..settings.clone()
+                        }));
+                    }
+                }
+            });
+
+        match &ui_state.output_buffer_sizes {
+            Some(supported) => {
+                // 0 uses the device's default buffer size. Dragging below the smallest supported size gets there.
+                let mut buffer_size_frames = settings.buffer_size_frames;
+                let buffer_size = ui.add(
+                    egui::DragValue::new(&mut buffer_size_frames)
+                        .range(supported.start().saturating_sub(1)..=*supported.end())
+                        .custom_formatter(|n, _| match n as u32 {
+                            n if n == 0 || n < *supported.start() => "Default".to_string(),
+                            n => format!("{} frames", n),
+                        }),
+                );
+                if buffer_size.changed() {
+                    if buffer_size_frames < *supported.start() {
+                        buffer_size_frames = 0;
+                    }
+                    events.push(Events::SetOutputSettings(OutputSettings {
+                        buffer_size_frames,
+                        ..settings.clone()
+                    }));
+                }
+                ui.label("Buffer size. Smaller is lower latency, but may crackle.");
+            }
+            None => {
+                ui.label("This device only uses its default buffer size.");
+            }
+        }
+        ui.small("Changes take effect after a restart.");
+    });
+}
+
+fn draw_midi_ports(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    ui.group(|ui| {
+        ui.label("MIDI Input");
+        let ports = &ui_state.midi_ports;
+        if ports.available.is_empty() {
+            ui.label("No MIDI devices found. Plug one in to play from it.");
+        }
+
+        let mut all_ports = ports.selected.is_none();
+        if ui.checkbox(&mut all_ports, "All devices").changed() {
+            // unticking keeps the devices that are connected now, so they can be picked from
+            let selected = if all_ports {
+                None
+            } else {
+                Some(ports.connected.clone())
+            };
+            events.push(Events::SetMidiPorts(selected));
+        }
+
+        let selected = ports.selected.clone().unwrap_or_default();
+        // ports that were chosen but are unplugged are listed too, so they can be unticked
+        let unplugged = selected
+            .iter()
+            .filter(|name| !ports.available.contains(name));
+        for name in ports.available.iter().chain(unplugged) {
+            let mut is_selected = all_ports || selected.contains(name);
+            let status = if ports.connected.contains(name) {
+                "connected"
+            } else if ports.available.contains(name) {
+                "not connected"
+            } else {
+                "unplugged"
+            };
+            let checkbox = ui.add_enabled(
+                !all_ports,
+                egui::Checkbox::new(&mut is_selected, format!("{} ({})", name, status)),
+            );
+            if checkbox.changed() {
+                let mut selected = selected.clone();
+                if is_selected {
+                    selected.push(name.clone());
+                } else {
+                    selected.retain(|n| n != name);
+                }
+                events.push(Events::SetMidiPorts(Some(selected)));
+            }
+        }
+    });
+}
+
+fn draw_midi_learn(ui: &mut egui::Ui, ui_state: &UIState, events: &mut Vec<Events>) {
+    ui.group(|ui| {
+        ui.label("MIDI Learn");
+        let Some(learn) = ui_state.midi_learn else {
+            if ui.button("Rebind a pad").clicked() {
+                events.push(Events::SetMidiLearn(Some(MidiLearn {
+                    instrument: None,
+                    replace: true,
+                })));
+            }
+            return;
+        };
+
+        match learn.instrument {
+            None => ui.label("Click an instrument's row in the beat grid."),
+            Some(instrument) => ui.label(format!("Hit a pad to play {:?}.", instrument)),
+        };
+        let mut replace = learn.replace;
+        if ui
+            .checkbox(&mut replace, "Replace the instrument's other pads")
+            .changed()
+        {
+            events.push(Events::SetMidiLearn(Some(MidiLearn { replace, ..learn })));
+        }
+        if ui.button("Cancel").clicked() {
+            events.push(Events::SetMidiLearn(None));
+        }
+    });
+}
+
 fn draw_central_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<Events>) {
     egui::CentralPanel::default().show(ctx, |ui| {
         draw_beat_grid(ui_state, ui, events);
@@ -410,7 +1124,6 @@ fn draw_central_panel(ctx: &egui::Context, ui_state: &UIState, events: &mut Vec<
 
 const VIRTUAL_WIDTH: f32 = 800.;
 const VIRTUAL_HEIGHT: f32 = 1000.;
-const WIDTH_SCALE: f32 = VIRTUAL_WIDTH / GRID_COLS as f32;
 const HEIGHT_SCALE: f32 = VIRTUAL_HEIGHT / GRID_ROWS as f32;
 
 fn draw_beat_grid(ui_state: &UIState, ui: &mut egui::Ui, events: &mut Vec<Events>) {
@@ -434,13 +1147,20 @@ fn draw_beat_grid(ui_state: &UIState, ui: &mut egui::Ui, events: &mut Vec<Events
         ),
     );
 
+    // the first shift-clicked step is kept until a second one picks the other end of the practice region
+    let anchor_id = ui.make_persistent_id("practice_region_anchor");
+    let mut anchor: Option<u32> = ui.data(|d| d.get_temp(anchor_id));
+
     // capture mouse clicks and toggle relevant beat
     ui.input(|i| {
         for event in &i.raw.events {
             match event {
                 // TODO: what is this syntax
                 egui::Event::PointerButton {
-                    pos, pressed: true, ..
+                    pos,
+                    button,
+                    pressed: true,
+                    modifiers,
                 } => {
                     // check if click is within the beat grid's bounds
                     if !response.rect.contains(*pos) {
@@ -450,65 +1170,163 @@ fn draw_beat_grid(ui_state: &UIState, ui: &mut egui::Ui, events: &mut Vec<Events
                     // Translate to (row, col)
                     let tpos = from_screen.transform_pos(*pos);
                     let row = (tpos.y * GRID_ROWS as f32 / VIRTUAL_HEIGHT) as usize;
-                    let col = (tpos.x * GRID_COLS as f32 / VIRTUAL_WIDTH) as usize;
+                    let col = (tpos.x * ui_state.num_grid_cols() as f32 / VIRTUAL_WIDTH) as usize;
                     info!(
                         "click at position = {:?} [[tpos = {:?}]] (row={:?}, col={:?})",
                         pos, tpos, row, col,
                     );
-                    events.push(Events::ToggleBeat {
-                        row: row as f64,
-                        beat: col as f64,
-                    });
+                    let beat = col as f64 / ui_state.grid_subdivision as f64;
+                    // a note that's between cells is shown in the nearest one, so clicking that cell acts on the note
+                    let note_beat = ui_state
+                        .enabled_beats
+                        .get(row)
+                        .and_then(|cells| cells.get(col).copied().flatten())
+                        .map_or(beat, |(note_beat, _)| note_beat);
+                    // while learning a MIDI pad, a click picks the instrument instead of changing the groove.
+                    // shift-click selects steps to practice, and right click changes the dynamic of an existing note
+                    if let Some(learn) = ui_state.midi_learn {
+                        if let Some(&instrument) = ALL_INSTRUMENTS.get(row) {
+                            events.push(Events::SetMidiLearn(Some(MidiLearn {
+                                instrument: Some(instrument),
+                                ..learn
+                            })));
+                        }
+                    } else if modifiers.shift {
+                        let step = (beat * ui_state.subdivision as f64).floor() as u32;
+                        match anchor.take() {
+                            Some(first_step) => {
+                                events.push(Events::SetPracticeRegion(Some(
+                                    PracticeRegion::from_steps(
+                                        first_step,
+                                        step,
+                                        ui_state.subdivision,
+                                    ),
+                                )));
+                            }
+                            None => anchor = Some(step),
+                        }
+                    } else if *button == egui::PointerButton::Secondary {
+                        events.push(Events::CycleDynamic {
+                            row: row as f64,
+                            beat: note_beat,
+                        });
+                    } else {
+                        events.push(Events::ToggleBeat {
+                            row: row as f64,
+                            beat: note_beat,
+                        });
+                    }
                 }
                 _ => (),
             }
         }
     });
+    match anchor {
+        Some(step) => ui.data_mut(|d| d.insert_temp(anchor_id, step)),
+        None => ui.data_mut(|d| d.remove::<u32>(anchor_id)),
+    }
 
     let beat_fill_color = if ui.visuals().dark_mode {
         Color32::from_rgb(50, 50, 50)
     } else {
         Color32::from_rgba_premultiplied(50, 50, 50, 128)
     };
+    let accent_fill_color = if ui.visuals().dark_mode {
+        Color32::from_rgb(120, 120, 120)
+    } else {
+        Color32::from_rgba_premultiplied(20, 20, 20, 220)
+    };
+
+    let width_scale = ui_state.grid_width_scale();
 
     let mut shapes = vec![];
     for row in 0..GRID_ROWS {
-        for col in 0..GRID_COLS {
-            let t_rect = rect_for_col_row(col, row, to_screen);
+        for col in 0..ui_state.num_grid_cols() {
+            let t_rect = rect_for_col_row(col, row, width_scale, to_screen);
 
             // if this beat is enabled (row is instrument, col is beat)..
-            if ui_state.enabled_beats[row][col] {
-                let shape =
-                    egui::Shape::rect_filled(t_rect, egui::Rounding::default(), beat_fill_color);
-                shapes.push(shape)
+            // accents are drawn darker, and ghost notes are drawn smaller
+            match ui_state.enabled_beats[row][col].map(|(_, dynamic)| dynamic) {
+                Some(Dynamic::Normal) => shapes.push(egui::Shape::rect_filled(
+                    t_rect,
+                    egui::Rounding::default(),
+                    beat_fill_color,
+                )),
+                Some(Dynamic::Accent) => shapes.push(egui::Shape::rect_filled(
+                    t_rect,
+                    egui::Rounding::default(),
+                    accent_fill_color,
+                )),
+                Some(Dynamic::Ghost) => shapes.push(egui::Shape::rect_filled(
+                    t_rect.shrink2(t_rect.size() / 4.),
+                    egui::Rounding::default(),
+                    beat_fill_color,
+                )),
+                None => (),
             }
 
-            let shape = egui::Shape::rect_stroke(
-                t_rect,
-                egui::Rounding::default(),
-                egui::Stroke::new(2., Color32::DARK_GRAY),
-            );
+            // sub-cells get a thinner outline than full steps
+            let stroke = if ui_state.cells_per_step() > 1 {
+                egui::Stroke::new(1., Color32::DARK_GRAY)
+            } else {
+                egui::Stroke::new(2., Color32::DARK_GRAY)
+            };
+            let shape = egui::Shape::rect_stroke(t_rect, egui::Rounding::default(), stroke);
             shapes.push(shape);
         }
     }
 
+    // outline each full step when it's split into sub-cells
+    let cells_per_step = ui_state.cells_per_step();
+    if cells_per_step > 1 {
+        for row in 0..GRID_ROWS {
+            for col in (0..ui_state.num_grid_cols()).step_by(cells_per_step) {
+                let t_rect =
+                    rect_for_col_row(col, row, width_scale, to_screen).union(rect_for_col_row(
+                        (col + cells_per_step - 1).min(ui_state.num_grid_cols() - 1),
+                        row,
+                        width_scale,
+                        to_screen,
+                    ));
+                let shape = egui::Shape::rect_stroke(
+                    t_rect,
+                    egui::Rounding::default(),
+                    egui::Stroke::new(2., Color32::DARK_GRAY),
+                );
+                shapes.push(shape);
+            }
+        }
+    }
+
     // Draw Note Successes
-    let loop_last_completed_beat = ui_state.current_beat - MISS_MARGIN as f32;
-    let current_loop_hits = get_hits_from_nth_loop(&ui_state.user_hits, ui_state.current_loop);
+    let loop_last_completed_beat =
+        ui_state.current_beat - ui_state.loop_start() as f32 - MISS_MARGIN as f32;
+    let current_loop_hits = get_hits_from_nth_loop(
+        &ui_state.user_hits,
+        ui_state.current_loop,
+        ui_state.loop_length,
+    );
     draw_note_successes(
+        ui_state,
         &current_loop_hits,
-        &ui_state.desired_hits,
-        ui_state.get_audio_latency_in_beats() as f64,
         loop_last_completed_beat as f64,
         to_screen,
         &mut shapes,
     );
 
+    draw_bar_lines(ui_state, to_screen, &mut shapes);
+    draw_practice_region_shade(ui_state, to_screen, &mut shapes);
+    if let Some(step) = anchor {
+        draw_practice_region_anchor(ui_state, step, to_screen, &mut shapes);
+    }
+    draw_midi_learn_row(ui_state, to_screen, &mut shapes);
+
     // Draw User Hits
     draw_user_hits(ui_state, to_screen, &mut shapes);
 
     draw_current_beat(
         ui_state.current_beat + ui_state.get_audio_latency_in_beats() as f32,
+        ui_state.beats_per_loop as f32,
         to_screen,
         ui,
         &mut shapes,
@@ -517,6 +1335,17 @@ fn draw_beat_grid(ui_state: &UIState, ui: &mut egui::Ui, events: &mut Vec<Events
     // render them
     painter.extend(shapes);
 
+    // show the count-in as a countdown over the grid
+    if let Some(remaining) = ui_state.count_in_remaining {
+        painter.text(
+            response.rect.center(),
+            egui::Align2::CENTER_CENTER,
+            format!("{}", remaining),
+            egui::FontId::proportional(response.rect.height() / 2.),
+            Color32::WHITE,
+        );
+    }
+
     // add instrument names last, so they stay visible
     for row in 0..GRID_ROWS {
         let name = match ALL_INSTRUMENTS[row] {
@@ -531,30 +1360,36 @@ fn draw_beat_grid(ui_state: &UIState, ui: &mut egui::Ui, events: &mut Vec<Events
             Instrument::Tom3 => "Tom3 (Low)",
             Instrument::PedalHiHat => "Pedal Hi-hat",
         };
-        let t_rect = rect_for_col_row(0, row, to_screen);
+        let t_rect = rect_for_col_row(0, row, width_scale, to_screen);
         let label = egui::Label::new(name);
         ui.put(t_rect, label);
     }
 }
 
-fn rect_for_col_row(col: usize, row: usize, to_screen: RectTransform) -> egui::Rect {
-    let base_pos = pos2(col as f32 * WIDTH_SCALE, row as f32 * HEIGHT_SCALE);
+fn rect_for_col_row(
+    col: usize,
+    row: usize,
+    width_scale: f32,
+    to_screen: RectTransform,
+) -> egui::Rect {
+    let base_pos = pos2(col as f32 * width_scale, row as f32 * HEIGHT_SCALE);
 
     // TODO: fix scaling to always draw a nicer looking square based grid
     let t_rect = to_screen.transform_rect(egui::Rect {
         min: base_pos,
-        max: base_pos + egui::Vec2::new(WIDTH_SCALE * 0.95, HEIGHT_SCALE * 0.95),
+        max: base_pos + egui::Vec2::new(width_scale * 0.95, HEIGHT_SCALE * 0.95),
     });
     t_rect
 }
 
 fn draw_current_beat(
     current_beat: f32,
+    beats_per_loop: f32,
     to_screen: RectTransform,
     ui: &mut egui::Ui,
     shapes: &mut Vec<Shape>,
 ) {
-    let base_pos = pos2((current_beat / BEATS_PER_LOOP as f32) * VIRTUAL_WIDTH, 0.);
+    let base_pos = pos2((current_beat / beats_per_loop) * VIRTUAL_WIDTH, 0.);
     let t_rect = to_screen.transform_rect(egui::Rect {
         min: base_pos,
         max: base_pos + egui::Vec2::new(2., VIRTUAL_HEIGHT),
@@ -571,14 +1406,18 @@ fn draw_current_beat(
 
 fn draw_user_hits(ui_state: &UIState, to_screen: RectTransform, shapes: &mut Vec<Shape>) {
     for (instrument_idx, instrument) in ALL_INSTRUMENTS.iter().enumerate() {
-        let user_notes = get_user_hit_timings_by_instrument(&ui_state.user_hits, *instrument);
+        let user_notes = get_user_hit_timings_by_instrument(
+            &ui_state.user_hits,
+            *instrument,
+            ui_state.loop_length,
+        );
         let desired_notes = ui_state.desired_hits.get_instrument_beats(instrument);
         for note in user_notes.iter() {
             draw_user_hit(
                 *note,
                 instrument_idx,
-                ui_state.get_audio_latency_in_beats() as f64,
-                desired_notes,
+                &desired_notes,
+                ui_state,
                 to_screen,
                 shapes,
             );
@@ -589,24 +1428,28 @@ fn draw_user_hits(ui_state: &UIState, to_screen: RectTransform, shapes: &mut Vec
 fn draw_user_hit(
     user_beat: f64,
     row: usize,
-    audio_latency_beats: f64,
     desired_hits: &Vec<f64>,
+    ui_state: &UIState,
     to_screen: RectTransform,
     shapes: &mut Vec<Shape>,
 ) {
-    let user_beat_with_latency = user_beat + audio_latency_beats;
+    let user_beat_with_latency = user_beat + ui_state.get_audio_latency_in_beats() as f64;
+    let loop_length = ui_state.loop_length;
 
-    let (acc, is_next_loop) = compute_accuracy_of_single_hit(user_beat_with_latency, desired_hits);
+    let (acc, is_next_loop) =
+        compute_accuracy_of_single_hit(user_beat_with_latency, desired_hits, loop_length);
 
     // with audio latency and is_next_loop
     // TODO(bug): hit a note on every beat of 16. Then toggle on and off a note on only beat 1 for that instrument. it causes buggy display of hit timings where the 2nd half (beats 9-16) aren't shown .. bercause it's closer to beat 1 than any other beat, I guess?.
     // TODO(ui): can't see "before" hits because there's no space to left anymore
-    let x = if is_next_loop {
-        ((user_beat_with_latency as f32 - BEATS_PER_LOOP as f32) / BEATS_PER_LOOP as f32)
-            * VIRTUAL_WIDTH
+    // hits are measured from the start of the practice region, while the grid shows the whole loop
+    let beat_in_loop = if is_next_loop {
+        user_beat_with_latency - loop_length
     } else {
-        (user_beat_with_latency as f32 / BEATS_PER_LOOP as f32) * VIRTUAL_WIDTH
+        user_beat_with_latency
     };
+    let x =
+        ((ui_state.loop_start() + beat_in_loop) / ui_state.beats_per_loop) as f32 * VIRTUAL_WIDTH;
 
     let base_pos = pos2(x as f32, row as f32 * HEIGHT_SCALE);
     let t_rect = to_screen.transform_rect(egui::Rect {
@@ -631,28 +1474,139 @@ fn draw_user_hit(
     shapes.push(shape);
 }
 
+/// draws a line at the start of each pulse of the meter, with a thicker line at the start of each bar
+fn draw_bar_lines(ui_state: &UIState, to_screen: RectTransform, shapes: &mut Vec<Shape>) {
+    let beats_per_bar = ui_state.time_signature.beats_per_bar();
+    let pulse = ui_state.time_signature.pulse_beats();
+
+    let mut bar_start = 0.;
+    while bar_start < ui_state.beats_per_loop {
+        let mut beat = bar_start;
+        while beat < bar_start + beats_per_bar && beat < ui_state.beats_per_loop {
+            let width = if beat == bar_start { 4. } else { 1. };
+            let x = (beat / ui_state.beats_per_loop) as f32 * VIRTUAL_WIDTH;
+            let t_rect = to_screen.transform_rect(egui::Rect {
+                min: pos2(x - width / 2., 0.),
+                max: pos2(x + width / 2., VIRTUAL_HEIGHT),
+            });
+            shapes.push(egui::Shape::rect_filled(
+                t_rect,
+                egui::Rounding::default(),
+                Color32::GRAY,
+            ));
+            beat += pulse;
+        }
+        bar_start += beats_per_bar;
+    }
+}
+
+/// dims the parts of the loop outside the practice region
+fn draw_practice_region_shade(
+    ui_state: &UIState,
+    to_screen: RectTransform,
+    shapes: &mut Vec<Shape>,
+) {
+    let Some(region) = ui_state.practice_region else {
+        return;
+    };
+    let to_x = |beat: f64| (beat / ui_state.beats_per_loop) as f32 * VIRTUAL_WIDTH;
+    let shade = Color32::from_black_alpha(160);
+    for (start, end) in [
+        (0., region.start_beat),
+        (region.end_beat, ui_state.beats_per_loop),
+    ] {
+        if end <= start {
+            continue;
+        }
+        let t_rect = to_screen.transform_rect(egui::Rect {
+            min: pos2(to_x(start), 0.),
+            max: pos2(to_x(end), VIRTUAL_HEIGHT),
+        });
+        shapes.push(egui::Shape::rect_filled(
+            t_rect,
+            egui::Rounding::default(),
+            shade,
+        ));
+    }
+}
+
+/// outlines the step picked as one end of the practice region, while waiting for the other
+fn draw_practice_region_anchor(
+    ui_state: &UIState,
+    step: u32,
+    to_screen: RectTransform,
+    shapes: &mut Vec<Shape>,
+) {
+    let to_x = |step: u32| {
+        (step as f64 / ui_state.subdivision as f64 / ui_state.beats_per_loop) as f32 * VIRTUAL_WIDTH
+    };
+    let t_rect = to_screen.transform_rect(egui::Rect {
+        min: pos2(to_x(step), 0.),
+        max: pos2(to_x(step + 1), VIRTUAL_HEIGHT),
+    });
+    shapes.push(egui::Shape::rect_stroke(
+        t_rect,
+        egui::Rounding::default(),
+        egui::Stroke::new(2., Color32::WHITE),
+    ));
+}
+
+/// outlines the row of the instrument that's being bound to a MIDI pad
+fn draw_midi_learn_row(ui_state: &UIState, to_screen: RectTransform, shapes: &mut Vec<Shape>) {
+    let Some(MidiLearn {
+        instrument: Some(instrument),
+        ..
+    }) = ui_state.midi_learn
+    else {
+        return;
+    };
+    let Some(row) = ALL_INSTRUMENTS.iter().position(|ins| *ins == instrument) else {
+        return;
+    };
+    let t_rect = to_screen.transform_rect(egui::Rect {
+        min: pos2(0., row as f32 * HEIGHT_SCALE),
+        max: pos2(VIRTUAL_WIDTH, (row + 1) as f32 * HEIGHT_SCALE),
+    });
+    shapes.push(egui::Shape::rect_stroke(
+        t_rect,
+        egui::Rounding::default(),
+        egui::Stroke::new(3., Color32::GOLD),
+    ));
+}
+
 fn draw_note_successes(
+    ui_state: &UIState,
     user_hits: &Vec<UserHit>,
-    desired_hits: &Voices,
-    audio_latency: f64,
     loop_current_beat: f64,
     to_screen: RectTransform,
     shapes: &mut Vec<Shape>,
 ) {
+    let audio_latency = ui_state.get_audio_latency_in_beats() as f64;
+    let beats_per_loop = ui_state.loop_length;
     for (instrument_idx, instrument) in ALL_INSTRUMENTS.iter().enumerate() {
-        let actual = get_user_hit_timings_by_instrument(user_hits, *instrument);
+        let actual = get_user_hit_timings_by_instrument(user_hits, *instrument, beats_per_loop);
         // add audio_latency to each note
         let actual_w_latency = actual
             .iter()
             .map(|note| note + audio_latency)
             .collect::<Vec<f64>>();
 
-        let desired = desired_hits.get_instrument_beats(instrument);
+        let desired = ui_state.desired_hits.get_instrument_beats(instrument);
 
-        let loop_perf =
-            compute_loop_performance_for_voice(&actual_w_latency, &desired, loop_current_beat);
+        let loop_perf = compute_loop_performance_for_voice(
+            &actual_w_latency,
+            &desired,
+            loop_current_beat,
+            beats_per_loop,
+        );
         for (note_idx, note) in desired.iter().enumerate() {
-            let shape = note_success_shape(*note, instrument_idx, loop_perf[note_idx], to_screen);
+            let shape = note_success_shape(
+                *note + ui_state.loop_start(),
+                instrument_idx,
+                loop_perf[note_idx],
+                ui_state,
+                to_screen,
+            );
             shapes.push(shape);
         }
     }
@@ -662,10 +1616,11 @@ fn note_success_shape(
     beats_offset: f64,
     row: usize,
     acc: Accuracy,
+    ui_state: &UIState,
     to_screen: RectTransform,
 ) -> Shape {
-    let col = beats_offset as usize; // TODO: truncate, for now
-    let rect = rect_for_col_row(col, row, to_screen);
+    let col = (beats_offset * ui_state.grid_subdivision as f64).round() as usize;
+    let rect = rect_for_col_row(col, row, ui_state.grid_width_scale(), to_screen);
 
     let bar_color = match acc {
         Accuracy::Early => ORANGE,
@@ -694,11 +1649,13 @@ fn gold_mode(ui: &mut egui::Ui, ui_state: &UIState) {
         let nth_loop_hits = get_hits_from_nth_loop(
             &ui_state.user_hits,
             (ui_state.current_loop as i32 - i) as usize, // TODO: check for overflow
+            ui_state.loop_length,
         );
         let summary_data = compute_last_loop_summary(
             &nth_loop_hits,
             &ui_state.desired_hits,
             ui_state.get_audio_latency_in_beats() as f64,
+            ui_state.loop_length,
         );
 
         // Simpler than chart.. TODO: support for colored emoji
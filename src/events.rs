@@ -1,4 +1,12 @@
-use crate::voices::Instrument;
+use crate::{
+    backing_track::BackingTrack,
+    game::MidiLearn,
+    metronome::{CountIn, GapClick, MetronomeSound, Pulse},
+    output_backend::OutputSettings,
+    practice_region::PracticeRegion,
+    speed_trainer::SpeedTrainerSettings,
+    voices::Instrument,
+};
 
 #[derive(Clone, Debug)]
 pub enum Events {
@@ -14,16 +22,51 @@ pub enum Events {
     Quit,
     ResetHits,
     SaveLoop,
+    /// renders the loop to a WAV file, repeated the given number of times
+    ExportWav {
+        repetitions: u32,
+    },
     ToggleBeat {
         row: f64,
         beat: f64,
     },
+    CycleDynamic {
+        row: f64,
+        beat: f64,
+    },
     TrackForCalibration,
     SetAudioLatency {
         delta_s: f64,
     },
     ToggleMetronome,
+    SetMetronomePulse(Pulse),
+    SetMetronomeSound(MetronomeSound),
+    ToggleMetronomeAccent,
+    SetCountIn(CountIn),
+    SetGapClick(GapClick),
+    SetInstrumentVolume {
+        instrument: Instrument,
+        volume: f64,
+    },
+    ToggleMute(Instrument),
+    ToggleSolo(Instrument),
+    SetMetronomeVolume(f64),
+    SetMasterVolume(f64),
+    ToggleHitSounds,
+    SetOutputSettings(OutputSettings),
+    SetHitVolume(f64),
+    /// loads a recording to play along with (or removes it)
+    SetBackingTrack(Option<BackingTrack>),
+    SetBackingTrackVolume(f64),
+    SetBackingTrackOffset(f64), // seconds
+    ToggleBackingTrackLoop,
+    ToggleSpeedTrainer,
+    SetSpeedTrainerSettings(SpeedTrainerSettings),
+    SetMidiLearn(Option<MidiLearn>),
+    SetMidiPorts(Option<Vec<String>>),
+    SetPracticeRegion(Option<PracticeRegion>),
     ChangeLoop(usize), // loop idx
+    ChangeKit(usize),  // kit idx
 
     ToggleHelpVisibility,
 
@@ -1,15 +1,19 @@
-use std::{fs::File, io::BufWriter, io::Write};
+use std::{fs::File, io::BufWriter, io::Write, path::Path};
 
 use std::error::Error;
 use std::sync::mpsc::Receiver;
 
-use crate::audio::Audio;
+use crate::audio::{Audio, AudioBackend};
 use crate::config::AppConfig;
-use crate::consts::TxMsg;
+use crate::consts::{TxMsg, DEFAULT_BEATS_PER_LOOP, RENDERS_DIR};
 use crate::egui_ui::UIState;
-use crate::score::compute_last_loop_summary;
+use crate::kit::Kit;
+use crate::output_backend::OutputDevices;
+use crate::render::export_wav;
+use crate::score::{compute_drift, compute_last_loop_summary, CORRECT_MARGIN, MISS_MARGIN};
+use crate::speed_trainer::SpeedTrainer;
 use crate::ui::*;
-use crate::voices::{Voices, VoicesFromJSON};
+use crate::voices::{Instrument, Voices, VoicesFromJSON};
 
 use log::info;
 use macroquad::prelude::*;
@@ -24,6 +28,36 @@ pub struct GoldMode {
     pub was_gold: bool,
 }
 
+/// MidiLearn binds the next pad that's hit to an instrument, so a device's mapping can be changed in the app.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MidiLearn {
+    /// the instrument to bind, once a row has been picked in the beat grid
+    pub instrument: Option<Instrument>,
+    /// replace the instrument's pads, instead of adding to them
+    pub replace: bool,
+}
+
+/// MidiPorts are the MIDI inputs to play from. Several can be connected at once, e.g. a kit and a pad controller.
+/// Ports are named without the numbers ALSA adds, which change when devices are plugged in (see `midi::port_device_name`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MidiPorts {
+    /// ports chosen by the user. None means every port. They're saved in `AppConfig`.
+    pub selected: Option<Vec<String>>,
+    /// ports that are plugged in, refreshed while the app runs
+    pub available: Vec<String>,
+    /// ports that are connected and being played from
+    pub connected: Vec<String>,
+}
+
+impl MidiPorts {
+    pub fn is_selected(&self, port_name: &str) -> bool {
+        match &self.selected {
+            Some(selected) => selected.iter().any(|name| name == port_name),
+            None => true,
+        }
+    }
+}
+
 pub struct Flags {
     pub ui_debug_mode: bool,
     pub dev_tools_visible: bool,
@@ -45,67 +79,104 @@ pub type Loops = Vec<(String, Loop)>;
 pub struct GameState {
     pub voices: Voices,
     pub gold_mode: GoldMode,
+    pub speed_trainer: SpeedTrainer,
+    /// while set, the next MIDI pad that's hit is bound instead of played
+    pub midi_learn: Option<MidiLearn>,
+    pub midi_ports: MidiPorts,
     pub selected_loop_idx: usize,
     pub loops: Loops,
+    pub kits: Vec<Kit>,
+    /// audio output devices found at startup
+    pub output_devices: OutputDevices,
     pub flags: Flags,
     pub correct_margin: f64,
     pub miss_margin: f64,
 }
 
 impl GameState {
-    pub fn new(loops: Loops) -> Self {
+    pub fn new(loops: Loops, kits: Vec<Kit>) -> Self {
         Self {
             voices: Voices::new(),
             gold_mode: GoldMode {
                 correct_takes: 0,
                 was_gold: false,
             },
+            speed_trainer: SpeedTrainer::default(),
+            midi_learn: None,
+            midi_ports: MidiPorts::default(),
             selected_loop_idx: 0,
             loops,
+            kits,
+            output_devices: OutputDevices::scan(),
             flags: Flags::new(),
-            correct_margin: 0.151,
-            miss_margin: 0.3,
+            correct_margin: CORRECT_MARGIN,
+            miss_margin: MISS_MARGIN,
         }
     }
 
-    pub fn new_mock_game_state() -> Self {
-        let voices_from_json = VoicesFromJSON::new_mock();
-        let voices = Voices::new_from_voices_old_model(&voices_from_json);
+    pub fn new_mock_game_state(kits: Vec<Kit>) -> Self {
+        let voices = Voices::new_from_voices_old_model(&VoicesFromJSON::new_mock());
         Self {
-            voices,
+            voices: voices.clone(),
             gold_mode: GoldMode {
                 correct_takes: 0,
                 was_gold: false,
             },
+            speed_trainer: SpeedTrainer::default(),
+            midi_learn: None,
+            midi_ports: MidiPorts::default(),
             selected_loop_idx: 0,
             loops: vec![(
                 "Foo".to_string(),
-                Loop {
-                    bpm: 112,
-                    voices: voices_from_json,
-                },
+                Loop::new("Foo", 112, DEFAULT_BEATS_PER_LOOP, &voices),
             )],
+            kits,
+            output_devices: OutputDevices::default(),
             flags: Flags::new(),
-            correct_margin: 0.151,
-            miss_margin: 0.3,
+            correct_margin: CORRECT_MARGIN,
+            miss_margin: MISS_MARGIN,
         }
     }
 }
 
 // TODO: simplify how we init this.. I don't think all the mutability and helper fns are needed
-pub fn compute_ui_state(gs: &GameState, audio: &Audio) -> UIState {
+pub fn compute_ui_state(gs: &GameState, audio: &Audio<impl AudioBackend>) -> UIState {
     let selector_vec = gs.loops.iter().map(|(name, _)| name.to_string()).collect();
     let mut ui_state = UIState::default().selector_vec(&selector_vec);
     ui_state.set_selected_idx(gs.selected_loop_idx);
+    ui_state.set_kit_names(gs.kits.iter().map(|kit| kit.name.clone()).collect());
+    ui_state.set_selected_kit_name(audio.get_kit_name());
     ui_state.set_current_beat(audio.current_beat());
     ui_state.set_current_loop(audio.current_loop() as usize);
-    ui_state.set_enabled_beats(&gs.voices);
+    ui_state.set_beats_per_loop(audio.get_beats_per_loop());
+    ui_state.set_time_signature(audio.get_time_signature());
+    ui_state.set_subdivision(audio.get_subdivision());
+    ui_state.set_enabled_beats(
+        &gs.voices,
+        audio.get_beats_per_loop(),
+        audio.get_subdivision(),
+    );
     ui_state.set_is_playing(!audio.is_paused());
+    ui_state.set_metronome(audio.get_metronome());
+    ui_state.set_gap_click(audio.get_gap_click());
+    ui_state.set_count_in(audio.get_count_in(), audio.count_in_remaining());
+    ui_state.set_mixer(audio.get_mixer());
+    ui_state.set_backing_track(audio.get_backing_track());
     ui_state.set_bpm(audio.get_bpm() as f32);
+    ui_state.set_speed_trainer(gs.speed_trainer);
     ui_state.set_audio_latency_s(audio.get_configured_audio_latency_seconds() as f32);
     ui_state.set_user_hits(&audio.user_hits);
-    ui_state.set_desired_hits(&gs.voices);
+    ui_state.set_practice_region(audio.get_practice_region(), audio.get_loop_length());
+    ui_state.set_desired_hits(&audio.voices_in_loop(&gs.voices));
     ui_state.set_metronome_enabled(audio.is_metronome_enabled());
+    ui_state.set_hit_sounds_enabled(audio.is_hit_sounds_enabled());
+    ui_state.set_midi_learn(gs.midi_learn);
+    ui_state.set_midi_ports(&gs.midi_ports);
+    ui_state.set_output(
+        &gs.output_devices,
+        audio.get_output_settings(),
+        audio.is_silent(),
+    );
 
     ui_state.set_is_dev_tools_visible(gs.flags.dev_tools_visible);
     ui_state.set_correct_margin(gs.correct_margin);
@@ -116,9 +187,10 @@ pub fn compute_ui_state(gs: &GameState, audio: &Audio) -> UIState {
 
 pub fn process_system_events(
     rx: &Receiver<TxMsg>,
-    audio: &mut Audio,
+    audio: &mut Audio<impl AudioBackend>,
     voices: &Voices,
     gold_mode: &mut GoldMode,
+    speed_trainer: &mut SpeedTrainer,
 ) {
     // read events
     loop {
@@ -129,16 +201,43 @@ pub fn process_system_events(
                     TxMsg::AudioNew => (),
                     TxMsg::StartingLoop(loop_num) => {
                         // TODO: UPDATE TO ONLY RUN THIS CODE FOR "on loop complete" events
+                        // only the practice region is scored, if there is one
+                        let loop_voices = audio.voices_in_loop(voices);
                         let last_loop_hits = get_hits_from_nth_loop(
                             &audio.user_hits,
                             (audio.current_loop() - 1) as usize,
+                            audio.get_loop_length(),
+                        );
+                        // scoring measures the latency in beats, like the UI does
+                        let audio_latency =
+                            audio.get_configured_audio_latency_seconds() * audio.get_bpm() / 60.;
+                        let summary_data = compute_last_loop_summary(
+                            &last_loop_hits,
+                            &loop_voices,
+                            audio_latency,
+                            audio.get_loop_length(),
                         );
-                        let audio_latency = audio.get_configured_audio_latency_seconds();
-                        let summary_data =
-                            compute_last_loop_summary(&last_loop_hits, &voices, audio_latency);
                         info!("last loop summary = {:?}", summary_data);
+                        if audio.get_gap_click().enabled {
+                            let drift = compute_drift(
+                                &audio.user_hits,
+                                &loop_voices,
+                                audio_latency,
+                                audio.get_loop_length(),
+                                &audio.get_gap_click(),
+                                &audio.get_time_signature(),
+                            );
+                            info!("gap click drift = {:?}", drift);
+                        }
                         let totals = summary_data.total();
 
+                        // the speed trainer advances regardless of score
+                        if loop_num > 0 {
+                            if let Some(bpm) = speed_trainer.on_loop_completed(audio.get_bpm()) {
+                                audio.set_bpm(bpm);
+                            }
+                        }
+
                         gold_mode.was_gold = false;
                         if totals.score() == 1. {
                             gold_mode.correct_takes += 1;
@@ -146,7 +245,10 @@ pub fn process_system_events(
                             gold_mode.correct_takes = 0;
                         }
 
-                        if gold_mode.correct_takes == GOLD_MODE_CORRECT_TAKES {
+                        // the speed trainer is in charge of the tempo while it's enabled
+                        if gold_mode.correct_takes == GOLD_MODE_CORRECT_TAKES
+                            && !speed_trainer.enabled
+                        {
                             audio.set_bpm(audio.get_bpm() + GOLD_MODE_BPM_STEP);
                             gold_mode.correct_takes = 0;
                             gold_mode.was_gold = true;
@@ -162,16 +264,20 @@ pub fn process_system_events(
 }
 
 /// update application state based on events (that came from user input)
-pub fn process_user_events(
+pub async fn process_user_events(
     voices: &mut Voices,
-    audio: &mut Audio,
+    audio: &mut Audio<impl AudioBackend>,
     flags: &mut Flags,
     loops: &Vec<(String, Loop)>,
+    kits: &[Kit],
     selected_loop_idx: &mut usize,
     events: &Vec<Events>,
     dir_name: &str,
     correct_margin: &mut f64,
     miss_margin: &mut f64,
+    speed_trainer: &mut SpeedTrainer,
+    midi_learn: &mut Option<MidiLearn>,
+    midi_ports: &mut MidiPorts,
 ) -> Result<(), Box<dyn Error>> {
     for event in events {
         info!("[user event] {:?}", event);
@@ -180,10 +286,11 @@ pub fn process_user_events(
                 instrument,
                 processing_delay,
             } => {
+                audio.play_hit_sound(*instrument)?;
                 audio.track_user_hit(*instrument, *processing_delay);
             }
             Events::Pause => {
-                audio.toggle_pause();
+                audio.toggle_pause()?;
             }
             Events::ChangeBPM { delta } => {
                 audio.set_bpm(audio.get_bpm() + delta);
@@ -200,15 +307,24 @@ pub fn process_user_events(
             Events::SaveLoop => {
                 // write serialized JSON output to a file
                 let dir_name = dir_name.trim_end_matches('/');
-                let file = File::create(format!("{}/loop-{}.json", dir_name, get_time()))?;
+                let name = format!("loop-{}", get_time());
+                let file = File::create(format!("{}/{}.json", dir_name, name))?;
                 let mut writer = BufWriter::new(file);
-                let my_loop = Loop {
-                    bpm: audio.get_bpm() as usize,
-                    voices: voices.to_voices_old_model(),
-                };
+                let my_loop = Loop::new(
+                    &name,
+                    audio.get_bpm() as usize,
+                    audio.get_beats_per_loop(),
+                    voices,
+                )
+                .with_meter(audio.get_time_signature(), audio.get_subdivision())
+                .with_backing_track(audio.get_backing_track().cloned());
                 serde_json::to_writer(&mut writer, &my_loop)?;
                 writer.flush()?;
             }
+            Events::ExportWav { repetitions } => {
+                let path = Path::new(RENDERS_DIR).join(format!("loop-{}.wav", get_time()));
+                export_wav(audio, voices, *repetitions, path);
+            }
             Events::ToggleBeat { row, beat } => {
                 // map from UI display to instrument
                 let res = ALL_INSTRUMENTS
@@ -222,23 +338,32 @@ pub fn process_user_events(
 
                 info!("toggling beat: {:?} {:?}", *ins, *beat);
                 voices.toggle_beat(*ins, *beat);
+                audio.reschedule();
+            }
+            Events::CycleDynamic { row, beat } => {
+                let ins = match ALL_INSTRUMENTS.get(*row as usize) {
+                    Some(x) => x,
+                    None => panic!("invalid instrument idx"),
+                };
+
+                info!("cycling dynamic: {:?} {:?}", *ins, *beat);
+                voices.cycle_dynamic(*ins, *beat);
+                audio.reschedule();
             }
             Events::TrackForCalibration => {
                 let updated_val = audio.track_for_calibration();
                 audio.set_configured_audio_latency_seconds(updated_val);
 
-                let cfg = AppConfig {
-                    audio_latency_seconds: updated_val,
-                };
+                let mut cfg = AppConfig::new();
+                cfg.audio_latency_seconds = updated_val;
                 cfg.save();
             }
             Events::SetAudioLatency { delta_s: delta } => {
                 let updated_val = audio.get_configured_audio_latency_seconds() + delta;
                 audio.set_configured_audio_latency_seconds(updated_val);
 
-                let cfg = AppConfig {
-                    audio_latency_seconds: updated_val,
-                };
+                let mut cfg = AppConfig::new();
+                cfg.audio_latency_seconds = updated_val;
                 cfg.save();
             }
             Events::ToggleDebugMode => {
@@ -247,15 +372,120 @@ pub fn process_user_events(
             Events::ToggleMetronome => {
                 audio.toggle_metronome();
             }
+            Events::SetCountIn(count_in) => {
+                audio.set_count_in(*count_in);
+            }
+            Events::SetGapClick(gap_click) => {
+                audio.set_gap_click(*gap_click);
+            }
+            Events::SetMetronomePulse(pulse) => {
+                audio.set_metronome_pulse(*pulse);
+            }
+            Events::SetMetronomeSound(sound) => {
+                audio.set_metronome_sound(*sound);
+            }
+            Events::ToggleMetronomeAccent => {
+                audio.toggle_metronome_accent();
+            }
+            Events::SetInstrumentVolume { instrument, volume } => {
+                audio.set_instrument_volume(instrument, *volume);
+            }
+            Events::ToggleMute(instrument) => {
+                audio.toggle_mute(instrument);
+            }
+            Events::ToggleSolo(instrument) => {
+                audio.toggle_solo(instrument);
+            }
+            Events::SetMetronomeVolume(volume) => {
+                audio.set_metronome_volume(*volume);
+            }
+            Events::SetMasterVolume(volume) => {
+                audio.set_master_volume(*volume);
+            }
+            Events::ToggleHitSounds => {
+                audio.toggle_hit_sounds();
+
+                let mut cfg = AppConfig::new();
+                cfg.hit_sounds_enabled = audio.is_hit_sounds_enabled();
+                cfg.save();
+            }
+            Events::SetOutputSettings(output_settings) => {
+                audio.set_output_settings(output_settings.clone());
+
+                let mut cfg = AppConfig::new();
+                cfg.output_device = output_settings.device_name.clone();
+                cfg.buffer_size_frames = output_settings.buffer_size_frames;
+                cfg.save();
+            }
+            Events::SetHitVolume(volume) => {
+                audio.set_hit_volume(*volume);
+            }
+            Events::SetBackingTrack(backing_track) => {
+                if let Err(e) = audio.set_backing_track(backing_track.clone()).await {
+                    log::error!("error loading backing track: {}", e);
+                }
+            }
+            Events::SetBackingTrackVolume(volume) => {
+                audio.set_backing_track_volume(*volume);
+            }
+            Events::SetBackingTrackOffset(offset_seconds) => {
+                audio.set_backing_track_offset(*offset_seconds)?;
+            }
+            Events::ToggleBackingTrackLoop => {
+                audio.toggle_backing_track_loop()?;
+            }
+            Events::ToggleSpeedTrainer => {
+                if speed_trainer.enabled {
+                    speed_trainer.stop();
+                } else {
+                    audio.set_bpm(speed_trainer.start());
+                }
+            }
+            Events::SetSpeedTrainerSettings(settings) => {
+                speed_trainer.settings = *settings;
+            }
+            Events::SetMidiLearn(learn) => {
+                *midi_learn = *learn;
+            }
+            Events::SetMidiPorts(ports) => {
+                midi_ports.selected = ports.clone();
+
+                let mut cfg = AppConfig::new();
+                cfg.midi_ports = ports.clone();
+                cfg.save();
+            }
+            Events::SetPracticeRegion(practice_region) => {
+                audio.set_practice_region(*practice_region)?;
+            }
             Events::ChangeLoop(loop_num) => {
                 // voices_options.iter().for_each(|(name, new_loop)| {
                 // if ui.button(None, format!("{:?} ({:?})", name.as_str(), new_loop.bpm)) {
                 let new_loop = loops.as_slice()[*loop_num].clone().1;
-                *voices = Voices::new_from_voices_old_model(&new_loop.voices);
-                audio.set_bpm(new_loop.bpm as f64);
+                *voices = new_loop.voices();
+                // the speed trainer keeps its tempo, so switching loops doesn't lose its progress
+                if !speed_trainer.enabled {
+                    audio.set_bpm(new_loop.bpm as f64);
+                }
+                audio.set_beats_per_loop(new_loop.length_beats)?;
+                audio.set_time_signature(new_loop.time_signature);
+                audio.set_subdivision(new_loop.subdivision);
+                audio.reschedule();
+                if let Err(e) = audio.set_backing_track(new_loop.backing_track).await {
+                    log::error!("error: {}", e);
+                }
 
                 *selected_loop_idx = *loop_num;
             }
+            Events::ChangeKit(kit_idx) => {
+                let kit = kits[*kit_idx].clone();
+                let mut cfg = AppConfig::new();
+                cfg.kit_name = kit.name.clone();
+                cfg.save();
+
+                if let Err(e) = audio.set_kit(kit).await {
+                    log::error!("error: {}", e);
+                }
+            }
             Events::ToggleDevToolsVisibility => {
                 flags.dev_tools_visible = !flags.dev_tools_visible;
             }
@@ -273,3 +503,22 @@ pub fn process_user_events(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::game::MidiPorts;
+
+    #[test]
+    fn it_selects_ports_by_device_name() {
+        let mut ports = MidiPorts::default();
+        assert!(ports.is_selected("TD-17 MIDI 1"));
+
+        ports.selected = Some(vec!["TD-17 MIDI 1".to_string()]);
+        assert!(ports.is_selected("TD-17 MIDI 1"));
+        assert!(!ports.is_selected("Pads"));
+
+        // choosing none is different from choosing every port
+        ports.selected = Some(vec![]);
+        assert!(!ports.is_selected("TD-17 MIDI 1"));
+    }
+}
@@ -0,0 +1,54 @@
+/*
+  A kira backend that renders audio on demand, instead of to a sound card.
+*/
+use kira::{
+    manager::backend::{
+        mock::{MockBackend, MockBackendSettings},
+        Backend, Renderer,
+    },
+    Frame,
+};
+
+/// HeadlessBackend renders only when asked to, so the clock only moves when it renders.
+/// It's used for tests, and for rendering loops to a file faster than real time.
+pub struct HeadlessBackend {
+    backend: MockBackend,
+    sample_rate: u32,
+}
+
+impl HeadlessBackend {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// renders the given amount of audio, which moves clocks forward and plays any sounds that are due
+    pub fn render(&mut self, seconds: f64) -> Vec<Frame> {
+        self.backend.on_start_processing();
+        let num_frames = (seconds * self.sample_rate as f64).round() as usize;
+        let frames = (0..num_frames).map(|_| self.backend.process()).collect();
+        // clock positions are only shared with handles at the start of each block
+        self.backend.on_start_processing();
+        frames
+    }
+}
+
+impl Backend for HeadlessBackend {
+    type Settings = MockBackendSettings;
+
+    type Error = ();
+
+    fn setup(settings: Self::Settings) -> Result<(Self, u32), Self::Error> {
+        let (backend, sample_rate) = MockBackend::setup(settings)?;
+        Ok((
+            Self {
+                backend,
+                sample_rate,
+            },
+            sample_rate,
+        ))
+    }
+
+    fn start(&mut self, renderer: Renderer) -> Result<(), Self::Error> {
+        self.backend.start(renderer)
+    }
+}
@@ -0,0 +1,123 @@
+/*
+  Drum kits, which map each instrument to the sample that's played for it.
+*/
+use std::{collections::HashMap, error::Error, io::Read, path::Path};
+
+use macroquad::file::load_file;
+use serde::{Deserialize, Serialize};
+
+use crate::voices::Instrument;
+
+/// the file describing a kit, found in the kit's directory
+pub const KIT_MANIFEST_FILE: &str = "kit.json";
+
+/// KitSample is the sound played for a single instrument.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KitSample {
+    /// path to the sample. In the manifest, this is relative to the kit's directory.
+    pub file: String,
+    /// multiplier applied to the sample's volume
+    #[serde(default = "default_gain")]
+    pub gain: f64,
+    /// -1 is hard left, 0 is center, 1 is hard right
+    #[serde(default)]
+    pub pan: f64,
+}
+
+fn default_gain() -> f64 {
+    1.
+}
+
+impl KitSample {
+    pub fn new(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            gain: default_gain(),
+            pan: 0.,
+        }
+    }
+
+    /// pan in the range used by the audio engine, where 0 is hard left and 1 is hard right
+    pub fn panning(&self) -> f64 {
+        (self.pan.clamp(-1., 1.) + 1.) / 2.
+    }
+}
+
+/// Kit is a directory of samples, described by a manifest (see `KIT_MANIFEST_FILE`).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Kit {
+    #[serde(default)]
+    pub name: String,
+    samples: HashMap<Instrument, KitSample>,
+}
+
+impl Kit {
+    pub async fn new_from_dir_async(dir: &str) -> Result<Self, Box<dyn Error>> {
+        log::info!("Kit::new_from_dir .. {}", dir);
+        let path = Path::new(dir).join(KIT_MANIFEST_FILE);
+        let f = load_file(&path.to_string_lossy()).await?;
+        Kit::new_from_reader(&*f, dir)
+    }
+
+    /// reads a kit manifest, resolving each sample's file relative to the kit's directory.
+    /// the directory name is used as the name if the kit doesn't have one.
+    fn new_from_reader(reader: impl Read, dir: &str) -> Result<Self, Box<dyn Error>> {
+        let mut kit: Kit = serde_json::from_reader(reader)?;
+
+        for sample in kit.samples.values_mut() {
+            sample.file = Path::new(dir)
+                .join(&sample.file)
+                .to_string_lossy()
+                .to_string();
+        }
+
+        if kit.name.is_empty() {
+            kit.name = Path::new(dir)
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string();
+        }
+
+        Ok(kit)
+    }
+
+    pub fn get_sample(&self, ins: &Instrument) -> Option<&KitSample> {
+        self.samples.get(ins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        consts::ALL_INSTRUMENTS,
+        kit::{Kit, KitSample},
+        voices::Instrument,
+    };
+
+    #[test]
+    fn it_loads_the_default_kit() {
+        let f = std::fs::File::open("res/kits/default/kit.json").unwrap();
+        let kit = Kit::new_from_reader(f, "res/kits/default").unwrap();
+        assert_eq!(kit.name, "Default");
+        for ins in ALL_INSTRUMENTS.iter() {
+            let sample = kit.get_sample(ins).unwrap();
+            assert!(std::path::Path::new(&sample.file).exists());
+        }
+    }
+
+    #[test]
+    fn it_resolves_samples_relative_to_the_kit_dir() {
+        let json = r#"{"samples": {"snare": {"file": "snare.wav", "pan": -0.5}}}"#;
+        let kit = Kit::new_from_reader(json.as_bytes(), "kits/brushes").unwrap();
+        assert_eq!(kit.name, "brushes");
+
+        let snare = kit.get_sample(&Instrument::Snare).unwrap();
+        assert_eq!(snare.file, "kits/brushes/snare.wav");
+        assert_eq!(snare.gain, 1.);
+        assert_eq!(snare.panning(), 0.25);
+        assert_eq!(KitSample::new("click.wav").panning(), 0.5);
+
+        assert!(kit.get_sample(&Instrument::Kick).is_none());
+    }
+}
@@ -1,21 +1,31 @@
 mod audio;
+mod backing_track;
 mod config;
 mod consts;
 mod egui_ui;
 mod events;
 mod fps;
 mod game;
+mod headless_backend;
 mod keyboard_input_handler;
+mod kit;
+mod metronome;
+mod mixer;
+mod output_backend;
+mod practice_region;
 
 #[cfg(not(target_arch = "wasm32"))]
 mod midi;
 #[cfg(not(target_arch = "wasm32"))]
 mod midi_input_handler;
 #[cfg(not(target_arch = "wasm32"))]
+mod midi_profile;
+#[cfg(not(target_arch = "wasm32"))]
 use midi_input_handler::MidiInputHandler;
 
+mod render;
 mod score;
-mod time;
+mod speed_trainer;
 mod ui;
 mod voices;
 
@@ -27,9 +37,10 @@ use crate::fps::FPS;
 use crate::ui::*;
 
 use audio::Audio;
-use consts::{WINDOW_HEIGHT, WINDOW_WIDTH};
+use consts::{KITS_DIR, WINDOW_HEIGHT, WINDOW_WIDTH};
 use game::{compute_ui_state, process_system_events, process_user_events, GameState, Loops};
 use keyboard_input_handler::KeyboardInputHandler;
+use kit::Kit;
 use simple_logger;
 
 use macroquad::prelude::*;
@@ -74,14 +85,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
+    // read drum kits
+    let kits = match read_kits(KITS_DIR).await {
+        Ok(kits) => kits,
+        Err(e) => {
+            log::error!(
+                "error: unable to read drum kits from directory ({}) due to '{}'",
+                KITS_DIR,
+                e
+            );
+            vec![]
+        }
+    };
+
     let keyboard_input = KeyboardInputHandler::new();
     #[cfg(not(target_arch = "wasm32"))]
     let mut midi_input = MidiInputHandler::new();
 
     let mut gs = if MOCK_INITIAL_STATE {
-        GameState::new_mock_game_state()
+        GameState::new_mock_game_state(kits)
     } else {
-        GameState::new(loops)
+        GameState::new(loops, kits)
     };
 
     // Setup audio, which runs on a separate thread and passes messages back.
@@ -90,12 +114,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // let conf = AppConfig::new()?; // TODO: Get rid of conf lib for now to simplify? This is the only usage
     let conf = AppConfig::new();
     log::debug!("App Config: {:?}", &conf);
+    gs.midi_ports.selected = conf.midi_ports.clone();
 
     let mut audio = if MOCK_INITIAL_STATE {
         Audio::new_mock(&conf, tx.clone())
     } else {
         Audio::new(&conf, tx.clone())
     };
+    // use the kit from the config, falling back to the first available kit
+    let kit = gs
+        .kits
+        .iter()
+        .find(|kit| kit.name == conf.kit_name)
+        .or(gs.kits.first());
+    match kit {
+        Some(kit) => {
+            if let Err(e) = audio.set_kit(kit.clone()).await {
+                log::error!("error: {}", e);
+            }
+        }
+        None => log::error!("error: no drum kits found in {}", KITS_DIR),
+    }
 
     // debug
     let mut fps_tracker = FPS::new();
@@ -108,23 +147,35 @@ async fn main() -> Result<(), Box<dyn Error>> {
         events.extend(ui.flush_events());
 
         #[cfg(not(target_arch = "wasm32"))]
-        events.extend(midi_input.process());
+        events.extend(midi_input.process(&mut gs.midi_ports, &mut gs.midi_learn));
 
         // change game state
-        process_system_events(&rx, &mut audio, &gs.voices, &mut gs.gold_mode);
+        process_system_events(
+            &rx,
+            &mut audio,
+            &gs.voices,
+            &mut gs.gold_mode,
+            &mut gs.speed_trainer,
+        );
         process_user_events(
             &mut gs.voices,
             &mut audio,
             &mut gs.flags,
             &gs.loops,
+            &gs.kits,
             &mut gs.selected_loop_idx,
             &events,
             &dir_name,
             &mut gs.correct_margin,
             &mut gs.miss_margin,
-        )?;
+            &mut gs.speed_trainer,
+            &mut gs.midi_learn,
+            &mut gs.midi_ports,
+        )
+        .await?;
 
-        audio.schedule(&gs.voices).await?;
+        audio.advance(get_frame_time() as f64);
+        audio.schedule(&gs.voices)?;
 
         // render UI
         ui.render(&compute_ui_state(&gs, &audio));
@@ -158,18 +209,7 @@ async fn read_loops(dir_name: &str) -> Result<Vec<(String, Loop)>, Box<dyn Error
     for path in &paths {
         let p = path.to_str().expect("unable to convert PathBuf to string");
         let v = Loop::new_from_file_async(p).await?;
-
-        // get just the file name from the path
-        let n = path
-            .file_name()
-            .expect("unable to get file name from path")
-            .to_str()
-            .expect("unable to convert OsStr to str");
-
-        // remove the file extension
-        let n = n.split(".json").next().expect("unable to split file name");
-
-        loops.push((n.to_string(), v));
+        loops.push((v.name.clone(), v));
     }
 
     // sort loops by name
@@ -177,3 +217,24 @@ async fn read_loops(dir_name: &str) -> Result<Vec<(String, Loop)>, Box<dyn Error
 
     Ok(loops)
 }
+
+/// reads every kit in the given directory, where each kit is a sub-directory with a manifest
+async fn read_kits(dir_name: &str) -> Result<Vec<Kit>, Box<dyn Error>> {
+    let paths = std::fs::read_dir(dir_name)?
+        .map(|res| res.map(|e| e.path()))
+        .collect::<Result<Vec<_>, std::io::Error>>()?;
+
+    let mut kits = Vec::<Kit>::new();
+    for path in paths.iter().filter(|p| p.is_dir()) {
+        let p = path.to_str().expect("unable to convert PathBuf to string");
+        match Kit::new_from_dir_async(p).await {
+            Ok(kit) => kits.push(kit),
+            Err(e) => log::error!("error: unable to read drum kit ({}) due to '{}'", p, e),
+        }
+    }
+
+    // sort kits by name
+    kits.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(kits)
+}
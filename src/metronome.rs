@@ -0,0 +1,287 @@
+/*
+  Metronome settings, and the clicks they produce over a loop.
+*/
+use crate::voices::{Dynamic, TimeSignature};
+
+/// Pulse is how often the metronome clicks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pulse {
+    /// the pulse that's counted in the time signature, e.g. dotted quarters in 6/8
+    Meter,
+    Quarter,
+    Eighth,
+    Sixteenth,
+}
+
+pub const ALL_PULSES: [Pulse; 4] = [
+    Pulse::Meter,
+    Pulse::Quarter,
+    Pulse::Eighth,
+    Pulse::Sixteenth,
+];
+
+impl Pulse {
+    /// time between clicks, in beats (quarter notes)
+    pub fn beats(&self, time_signature: &TimeSignature) -> f64 {
+        match self {
+            Pulse::Meter => time_signature.pulse_beats(),
+            Pulse::Quarter => 1.,
+            Pulse::Eighth => 0.5,
+            Pulse::Sixteenth => 0.25,
+        }
+    }
+}
+
+/// MetronomeSound is the sample played for each click.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetronomeSound {
+    Click,
+    Classic,
+}
+
+pub const ALL_METRONOME_SOUNDS: [MetronomeSound; 2] =
+    [MetronomeSound::Click, MetronomeSound::Classic];
+
+impl MetronomeSound {
+    pub fn get_audio_file(&self) -> &'static str {
+        match self {
+            MetronomeSound::Click => "res/sounds/click.wav",
+            MetronomeSound::Classic => "res/sounds/metronome.ogg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metronome {
+    pub pulse: Pulse,
+    pub sound: MetronomeSound,
+    /// play the first click of each bar louder
+    pub accent_downbeat: bool,
+}
+
+impl Default for Metronome {
+    fn default() -> Self {
+        Self {
+            pulse: Pulse::Meter,
+            sound: MetronomeSound::Click,
+            accent_downbeat: true,
+        }
+    }
+}
+
+impl Metronome {
+    /// the beats on which the metronome clicks, restarting each bar, along with how loud each click is
+    pub fn clicks(
+        &self,
+        time_signature: &TimeSignature,
+        beats_per_loop: f64,
+    ) -> Vec<(f64, Dynamic)> {
+        let mut out = vec![];
+        let beats_per_bar = time_signature.beats_per_bar();
+        let pulse = self.pulse.beats(time_signature);
+        let mut bar_start = 0.;
+        while bar_start < beats_per_loop {
+            let mut beat = bar_start;
+            while beat < (bar_start + beats_per_bar).min(beats_per_loop) {
+                let dynamic = if self.accent_downbeat && beat == bar_start {
+                    Dynamic::Accent
+                } else {
+                    Dynamic::Normal
+                };
+                out.push((beat, dynamic));
+                beat += pulse;
+            }
+            bar_start += beats_per_bar;
+        }
+        out
+    }
+}
+
+/// CountInUnit is what the length of a count-in is measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountInUnit {
+    /// beats of the meter, e.g. dotted quarters in 6/8
+    Beats,
+    Bars,
+}
+
+pub const ALL_COUNT_IN_UNITS: [CountInUnit; 2] = [CountInUnit::Beats, CountInUnit::Bars];
+
+/// CountIn is the clicks played before playback starts or resumes, so the first note can be played in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CountIn {
+    /// 0 disables the count-in
+    pub length: u32,
+    pub unit: CountInUnit,
+}
+
+impl Default for CountIn {
+    fn default() -> Self {
+        Self {
+            length: 1,
+            unit: CountInUnit::Bars,
+        }
+    }
+}
+
+impl CountIn {
+    /// length of the count-in, in beats (quarter notes)
+    pub fn length_beats(&self, time_signature: &TimeSignature) -> f64 {
+        let unit = match self.unit {
+            CountInUnit::Beats => time_signature.pulse_beats(),
+            CountInUnit::Bars => time_signature.beats_per_bar(),
+        };
+        self.length as f64 * unit
+    }
+
+    /// the beats (from the start of the count-in) on which it clicks, on each beat of the meter.
+    /// the first click of each bar is accented.
+    pub fn clicks(&self, time_signature: &TimeSignature) -> Vec<(f64, Dynamic)> {
+        let metronome = Metronome {
+            pulse: Pulse::Meter,
+            accent_downbeat: true,
+            ..Metronome::default()
+        };
+        metronome.clicks(time_signature, self.length_beats(time_signature))
+    }
+}
+
+/// GapClick silences the metronome, and optionally the target notes, for some bars
+/// so the player practices keeping time on their own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GapClick {
+    pub enabled: bool,
+    /// bars that are heard at the start of each cycle
+    pub audible_bars: u32,
+    /// bars that follow in silence
+    pub silent_bars: u32,
+    /// silence the target notes too, not just the metronome
+    pub silence_voices: bool,
+}
+
+impl Default for GapClick {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            audible_bars: 2,
+            silent_bars: 2,
+            silence_voices: false,
+        }
+    }
+}
+
+impl GapClick {
+    /// whether sounds at the given groove beat are heard. Cycles start from the first bar of the groove.
+    pub fn is_audible(&self, beat: f64, time_signature: &TimeSignature) -> bool {
+        if !self.enabled || self.silent_bars == 0 {
+            return true;
+        }
+        let cycle = (self.audible_bars + self.silent_bars) as i64;
+        let bar = (beat / time_signature.beats_per_bar()).floor() as i64;
+        bar.rem_euclid(cycle) < self.audible_bars as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        metronome::{CountIn, CountInUnit, GapClick, Metronome, Pulse},
+        voices::{Dynamic, TimeSignature},
+    };
+
+    #[test]
+    fn it_accents_the_downbeat_of_each_bar() {
+        let metronome = Metronome::default();
+        let clicks = metronome.clicks(&TimeSignature::default(), 8.);
+        assert_eq!(clicks.len(), 8);
+        assert_eq!(clicks[0], (0., Dynamic::Accent));
+        assert_eq!(clicks[1], (1., Dynamic::Normal));
+        assert_eq!(clicks[4], (4., Dynamic::Accent));
+
+        let metronome = Metronome {
+            accent_downbeat: false,
+            ..Metronome::default()
+        };
+        let clicks = metronome.clicks(&TimeSignature::default(), 8.);
+        assert!(clicks
+            .iter()
+            .all(|(_, dynamic)| *dynamic == Dynamic::Normal));
+    }
+
+    #[test]
+    fn it_clicks_on_the_selected_pulse() {
+        let six_eight = TimeSignature {
+            numerator: 6,
+            denominator: 8,
+        };
+        let beats = |pulse| {
+            let metronome = Metronome {
+                pulse,
+                ..Metronome::default()
+            };
+            metronome
+                .clicks(&six_eight, 3.)
+                .iter()
+                .map(|(beat, _)| *beat)
+                .collect::<Vec<f64>>()
+        };
+        assert_eq!(beats(Pulse::Meter), vec![0., 1.5]);
+        assert_eq!(beats(Pulse::Quarter), vec![0., 1., 2.]);
+        assert_eq!(beats(Pulse::Eighth), vec![0., 0.5, 1., 1.5, 2., 2.5]);
+        assert_eq!(beats(Pulse::Sixteenth).len(), 12);
+    }
+
+    #[test]
+    fn it_counts_in_by_beats_or_bars() {
+        let three_four = TimeSignature {
+            numerator: 3,
+            denominator: 4,
+        };
+        let count_in = CountIn {
+            length: 2,
+            unit: CountInUnit::Bars,
+        };
+        assert_eq!(count_in.length_beats(&three_four), 6.);
+        let clicks = count_in.clicks(&three_four);
+        assert_eq!(clicks.len(), 6);
+        assert_eq!(clicks[3], (3., Dynamic::Accent));
+
+        let count_in = CountIn {
+            length: 2,
+            unit: CountInUnit::Beats,
+        };
+        assert_eq!(count_in.length_beats(&three_four), 2.);
+        assert_eq!(count_in.clicks(&three_four).len(), 2);
+
+        let count_in = CountIn {
+            length: 0,
+            unit: CountInUnit::Bars,
+        };
+        assert!(count_in.clicks(&three_four).is_empty());
+    }
+
+    #[test]
+    fn it_alternates_audible_and_silent_bars() {
+        let gap_click = GapClick {
+            enabled: true,
+            audible_bars: 1,
+            silent_bars: 2,
+            ..GapClick::default()
+        };
+        let four_four = TimeSignature::default();
+        let audible = |beat| gap_click.is_audible(beat, &four_four);
+        assert!(audible(0.));
+        assert!(audible(3.75));
+        assert!(!audible(4.));
+        assert!(!audible(11.5));
+        assert!(audible(12.));
+        // early hits for the first note land just before the groove starts, in a silent bar
+        assert!(!audible(-0.1));
+
+        let disabled = GapClick {
+            enabled: false,
+            ..gap_click
+        };
+        assert!(disabled.is_audible(4., &four_four));
+    }
+}
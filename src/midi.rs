@@ -4,262 +4,294 @@
  Thanks to: https://github.com/TanTanDev/midi_game
 */
 
-use log::info;
 use midir;
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::error::Error;
 use std::string::*;
-use std::sync::{Arc, Mutex};
-
-use crate::time::current_time_millis;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
 
 pub struct MidiInput {
-    input_port: midir::MidiInputPort,
     device_name: String,
-    // optional because it needs to be consumed and sent to the connection thread
-    midi_input: Option<midir::MidiInput>,
-    connection: Option<midir::MidiInputConnection<()>>,
+    /// the device stays connected for as long as this is kept. Dropping it disconnects.
+    _connection: midir::MidiInputConnection<()>,
+    /// messages are queued by the connection thread in the order they arrive, and drained each frame.
+    /// Sending never blocks the connection thread, and repeated hits on the same pad are all kept.
+    events_rx: Receiver<MidiInputDataRaw>,
+}
+
+/// names of the MIDI input ports that can be connected to right now
+pub fn midi_port_names() -> Vec<String> {
+    match midir::MidiInput::new("Port scan") {
+        Ok(midi_input) => midi_input
+            .ports()
+            .iter()
+            .filter_map(|port| midi_input.port_name(port).ok())
+            .collect(),
+        Err(e) => {
+            log::error!("error: unable to list midi devices due to '{}'", e);
+            vec![]
+        }
+    }
+}
 
-    raw_inputs: Arc<Mutex<HashMap<u8, MidiInputDataRaw>>>,
-    previous_raw_inputs: Arc<Mutex<HashMap<u8, MidiInputDataRaw>>>,
+/// the port's name without the client and port numbers ALSA ends it with, e.g. "TD-17:TD-17 MIDI 1 20:0" is
+/// "TD-17:TD-17 MIDI 1". The numbers can change whenever devices are plugged in, so saved names leave them out.
+pub fn port_device_name(port_name: &str) -> &str {
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    match port_name.rsplit_once(' ') {
+        Some((name, ids))
+            if ids
+                .split_once(':')
+                .is_some_and(|(client, port)| is_number(client) && is_number(port)) =>
+        {
+            name.trim_end()
+        }
+        _ => port_name,
+    }
 }
 
 #[derive(Eq, Clone, Debug, Copy, PartialEq)]
 pub struct MidiInputDataRaw {
     pub note_number: u8,
+    /// microseconds, from the driver's own clock. It's precise, but starts at an arbitrary point.
     pub timestamp: u64,
-    pub non_midi_timestamp_ms: u128,
+    /// when the message was received
+    pub received: Instant,
     // https://www.logosfoundation.org/kursus/1075.html
     status: u8,
     note_velocity: u8,
 }
 
 impl MidiInputDataRaw {
+    /// reads a message from the device. Returns None for messages without a note and velocity, like timing clocks.
+    pub fn from_message(stamp: u64, message: &[u8], received: Instant) -> Option<Self> {
+        let [status, note_number, note_velocity, ..] = *message else {
+            return None;
+        };
+        Some(Self {
+            note_number,
+            timestamp: stamp,
+            received,
+            status,
+            note_velocity,
+        })
+    }
+
     pub fn is_note_on(&self) -> bool {
-        self.status >= 144 && self.status <= 159
+        // a note on with no velocity is how many devices send a note off
+        self.status >= 144 && self.status <= 159 && self.note_velocity > 0
     }
 }
 
 impl MidiInput {
-    pub fn new() -> Option<Self> {
-        let midi_input = midir::MidiInput::new("Input device").unwrap();
-        // grab first device
-        let input_port = match midi_input.ports().into_iter().next() {
-            Some(port) => port,
-            None => return None,
-        };
+    /// connects to the input port with this name
+    pub fn connect(port_name: &str) -> Result<Self, Box<dyn Error>> {
+        let midi_input = midir::MidiInput::new("Input device")?;
+        let input_port = midi_input
+            .ports()
+            .into_iter()
+            .find(|port| {
+                midi_input
+                    .port_name(port)
+                    .is_ok_and(|name| name == port_name)
+            })
+            .ok_or("midi device not found")?;
 
-        let device_name = midi_input
-            .port_name(&input_port)
-            .expect("can't get name of port");
+        log::info!("Connecting to midi device: {}", port_name);
+        let (events_tx, events_rx) = mpsc::channel();
+        let connection = midi_input
+            .connect(
+                &input_port,
+                port_name,
+                move |stamp, message, _| {
+                    let Some(v) = MidiInputDataRaw::from_message(stamp, message, Instant::now())
+                    else {
+                        return;
+                    };
+                    // the receiver only goes away when the device is disconnected
+                    let _ = events_tx.send(v);
+                },
+                (),
+            )
+            .map_err(|e| e.to_string())?;
 
-        Some(Self {
-            midi_input: Some(midi_input),
-            input_port,
-            device_name,
-            connection: None,
-            raw_inputs: Arc::new(Mutex::new(HashMap::with_capacity(16))),
-            previous_raw_inputs: Arc::new(Mutex::new(HashMap::with_capacity(16))),
+        Ok(Self {
+            device_name: port_name.to_string(),
+            _connection: connection,
+            events_rx,
         })
     }
 
+    /// drains the notes that were pressed since the last call, in the order they arrived
     pub fn get_pressed_buttons(&self) -> Vec<MidiInputDataRaw> {
-        let mut pressed = Vec::new();
-        let mut raw_inputs = self.raw_inputs.lock().unwrap();
-        for (_id, raw_input) in raw_inputs.iter_mut() {
-            if raw_input.is_note_on() {
-                pressed.push(*raw_input);
-            }
-        }
+        let pressed = drain_pressed(&self.events_rx);
         if pressed.len() > 0 {
             log::info!("Pressed midi: {:?}", pressed);
         }
         pressed
     }
 
-    // clear all inputs, update previous values
-    pub fn flush(&mut self) {
-        let mut prev_raw_inputs = self.previous_raw_inputs.lock().unwrap();
-        let mut raw_inputs = self.raw_inputs.lock().unwrap();
-        // store latests values as previous
-        for (id, raw_input) in raw_inputs.iter_mut() {
-            if let Some(prev_raw) = prev_raw_inputs.get_mut(&id) {
-                *prev_raw = *raw_input;
-            } else {
-                prev_raw_inputs.insert(*id, *raw_input);
+    pub fn get_device_name(&self) -> &str {
+        &self.device_name
+    }
+}
+
+/// MidiClock converts the driver's timestamps to when hits were struck, so they can be placed between frames.
+///
+/// The driver's clock starts at an arbitrary point, so its offset from the system's clock is estimated from
+/// when messages are received. Delivery can only delay a message, so the smallest recent offset is the most accurate.
+/// Only recent messages count, so the estimate follows the two clocks drifting apart.
+#[derive(Debug, Default)]
+pub struct MidiClock {
+    /// receive times are measured from the first message
+    start: Option<Instant>,
+    /// (driver timestamp, offset) of recent messages in microseconds. Offsets increase, so the first is the smallest.
+    offsets_us: VecDeque<(u64, i128)>,
+}
+
+/// how long, in the driver's microseconds, a message's offset is kept
+const MIDI_CLOCK_WINDOW_US: u64 = 10_000_000;
+
+impl MidiClock {
+    /// refines the offset using a message's timestamp and when it was received
+    pub fn observe(&mut self, input: &MidiInputDataRaw) {
+        let start = *self.start.get_or_insert(input.received);
+        let offset =
+            input.received.duration_since(start).as_micros() as i128 - input.timestamp as i128;
+        // a larger offset from an older message can't be the smallest again
+        while self
+            .offsets_us
+            .back()
+            .is_some_and(|(_, prev)| *prev >= offset)
+        {
+            self.offsets_us.pop_back();
+        }
+        self.offsets_us.push_back((input.timestamp, offset));
+        while self
+            .offsets_us
+            .front()
+            .is_some_and(|(stamp, _)| stamp + MIDI_CLOCK_WINDOW_US < input.timestamp)
+        {
+            self.offsets_us.pop_front();
+        }
+    }
+
+    /// when the message was sent by the device
+    pub fn struck_at(&self, input: &MidiInputDataRaw) -> Instant {
+        match (self.start, self.offsets_us.front()) {
+            (Some(start), Some((_, offset))) => {
+                let since_start_us = input.timestamp as i128 + offset;
+                let since_start = Duration::from_micros(since_start_us.unsigned_abs() as u64);
+                if since_start_us >= 0 {
+                    start + since_start
+                } else {
+                    start.checked_sub(since_start).unwrap_or(start)
+                }
             }
+            _ => input.received,
         }
-        raw_inputs.clear();
     }
+}
+
+/// takes every queued message, keeping the note ons
+fn drain_pressed(events_rx: &Receiver<MidiInputDataRaw>) -> Vec<MidiInputDataRaw> {
+    events_rx
+        .try_iter()
+        .filter(|event| event.is_note_on())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use crate::midi::{drain_pressed, port_device_name, MidiClock, MidiInputDataRaw};
+
+    #[test]
+    fn it_keeps_every_hit_in_arrival_order() {
+        let (tx, rx) = mpsc::channel();
+        let messages: [(u64, [u8; 3]); 5] = [
+            (100, [153, 36, 90]), // kick
+            (105, [137, 36, 0]),  // kick off
+            (110, [153, 36, 80]), // kick again, within the same frame
+            (112, [153, 38, 0]),  // snare off, sent as a note on without velocity
+            (115, [153, 38, 70]), // snare
+        ];
+        for (stamp, message) in messages {
+            tx.send(MidiInputDataRaw::from_message(stamp, &message, Instant::now()).unwrap())
+                .unwrap();
+        }
 
-    pub fn connect(&mut self) {
-        log::info!("Connecting to midi device: {}", self.device_name);
-        let raw_inputs = self.raw_inputs.clone();
-        self.connection = Some(
-            self.midi_input
-                .take() // consume midi_input because it will be sent to thread
-                .unwrap()
-                .connect(
-                    &self.input_port,
-                    self.device_name.as_str(),
-                    move |stamp, message, _| {
-                        // get timestamp
-                        let non_midi_timestamp_ms = current_time_millis();
-                        let midi_function = message[0];
-                        let note_number = message[1];
-                        let v = MidiInputDataRaw {
-                            note_number,
-                            timestamp: stamp,
-                            non_midi_timestamp_ms,
-                            status: midi_function,
-                            note_velocity: message[2],
-                        };
-                        info!("{}: {:?} (len = {})", stamp, v, message.len());
-                        info!("{}", MIDI_FUNCTION_NAMES[midi_function as usize - 128]);
-                        let mut rw: std::sync::MutexGuard<HashMap<u8, MidiInputDataRaw>> =
-                            raw_inputs.lock().unwrap();
-                        rw.insert(note_number, v);
-                    },
-                    (),
-                )
-                .expect("can't connect to midi device"),
+        let pressed: Vec<(u64, u8)> = drain_pressed(&rx)
+            .iter()
+            .map(|hit| (hit.timestamp, hit.note_number))
+            .collect();
+        assert_eq!(pressed, vec![(100, 36), (110, 36), (115, 38)]);
+        // draining empties the queue
+        assert!(drain_pressed(&rx).is_empty());
+    }
+
+    #[test]
+    fn it_ignores_messages_without_a_note() {
+        // timing clock
+        assert_eq!(
+            MidiInputDataRaw::from_message(0, &[248], Instant::now()),
+            None
         );
     }
 
-    pub fn get_device_name(&self) -> &str {
-        &self.device_name
+    #[test]
+    fn it_leaves_alsa_ids_out_of_port_names() {
+        assert_eq!(
+            port_device_name("TD-17:TD-17 MIDI 1 20:0"),
+            "TD-17:TD-17 MIDI 1"
+        );
+        assert_eq!(port_device_name("TD-17 MIDI 1"), "TD-17 MIDI 1");
+        assert_eq!(port_device_name("Pads 2:"), "Pads 2:");
     }
-}
 
-// Midi Spec
+    #[test]
+    fn it_places_hits_by_the_driver_timestamp() {
+        let mut clock = MidiClock::default();
+        let start = Instant::now();
+        let at = |us: u64| start + Duration::from_micros(us);
+        // the driver's clock started 1s before the first message was received.
+        // the first message waited 5ms to be delivered, and the second only 1ms.
+        let first = MidiInputDataRaw::from_message(1_000_000, &[153, 36, 90], at(0)).unwrap();
+        let second = MidiInputDataRaw::from_message(1_010_000, &[153, 36, 90], at(6_000)).unwrap();
+        clock.observe(&first);
+        assert_eq!(clock.struck_at(&first), at(0));
+        clock.observe(&second);
+        assert_eq!(clock.struck_at(&second), at(6_000));
+        assert_eq!(
+            clock.struck_at(&first),
+            at(0) - Duration::from_micros(4_000)
+        );
+    }
 
-// from 128-255, these are the functions corresponding to a Midi Note's 1st byte
-const MIDI_FUNCTION_NAMES: [&str; 128] = [
-    "Chan 1 Note off",
-    "Chan 2 Note off",
-    "Chan 3 Note off",
-    "Chan 4 Note off",
-    "Chan 5 Note off",
-    "Chan 6 Note off",
-    "Chan 7 Note off",
-    "Chan 8 Note off",
-    "Chan 9 Note off",
-    "Chan 10 Note off",
-    "Chan 11 Note off",
-    "Chan 12 Note off",
-    "Chan 13 Note off",
-    "Chan 14 Note off",
-    "Chan 15 Note off",
-    "Chan 16 Note off",
-    "Chan 1 Note on",
-    "Chan 2 Note on",
-    "Chan 3 Note on",
-    "Chan 4 Note on",
-    "Chan 5 Note on",
-    "Chan 6 Note on",
-    "Chan 7 Note on",
-    "Chan 8 Note on",
-    "Chan 9 Note on",
-    "Chan 10 Note on",
-    "Chan 11 Note on",
-    "Chan 12 Note on",
-    "Chan 13 Note on",
-    "Chan 14 Note on",
-    "Chan 15 Note on",
-    "Chan 16 Note on",
-    "Chan 1 Polyphonic Aftertouch",
-    "Chan 2 Polyphonic Aftertouch",
-    "Chan 3 Polyphonic Aftertouch",
-    "Chan 4 Polyphonic Aftertouch",
-    "Chan 5 Polyphonic Aftertouch",
-    "Chan 6 Polyphonic Aftertouch",
-    "Chan 7 Polyphonic Aftertouch",
-    "Chan 8 Polyphonic Aftertouch",
-    "Chan 9 Polyphonic Aftertouch",
-    "Chan 10 Polyphonic Aftertouch",
-    "Chan 11 Polyphonic Aftertouch",
-    "Chan 12 Polyphonic Aftertouch",
-    "Chan 13 Polyphonic Aftertouch",
-    "Chan 14 Polyphonic Aftertouch",
-    "Chan 15 Polyphonic Aftertouch",
-    "Chan 16 Polyphonic Aftertouch",
-    "Chan 1 Control/Mode Change",
-    "Chan 2 Control/Mode Change",
-    "Chan 3 Control/Mode Change",
-    "Chan 4 Control/Mode Change",
-    "Chan 5 Control/Mode Change",
-    "Chan 6 Control/Mode Change",
-    "Chan 7 Control/Mode Change",
-    "Chan 8 Control/Mode Change",
-    "Chan 9 Control/Mode Change",
-    "Chan 10 Control/Mode Change",
-    "Chan 11 Control/Mode Change",
-    "Chan 12 Control/Mode Change",
-    "Chan 13 Control/Mode Change",
-    "Chan 14 Control/Mode Change",
-    "Chan 15 Control/Mode Change",
-    "Chan 16 Control/Mode Change",
-    "Chan 1 Program Change",
-    "Chan 2 Program Change",
-    "Chan 3 Program Change",
-    "Chan 4 Program Change",
-    "Chan 5 Program Change",
-    "Chan 6 Program Change",
-    "Chan 7 Program Change",
-    "Chan 8 Program Change",
-    "Chan 9 Program Change",
-    "Chan 10 Program Change",
-    "Chan 11 Program Change",
-    "Chan 12 Program Change",
-    "Chan 13 Program Change",
-    "Chan 14 Program Change",
-    "Chan 15 Program Change",
-    "Chan 16 Program Change",
-    "Chan 1 Channel Aftertouch",
-    "Chan 2 Channel Aftertouch",
-    "Chan 3 Channel Aftertouch",
-    "Chan 4 Channel Aftertouch",
-    "Chan 5 Channel Aftertouch",
-    "Chan 6 Channel Aftertouch",
-    "Chan 7 Channel Aftertouch",
-    "Chan 8 Channel Aftertouch",
-    "Chan 9 Channel Aftertouch",
-    "Chan 10 Channel Aftertouch",
-    "Chan 11 Channel Aftertouch",
-    "Chan 12 Channel Aftertouch",
-    "Chan 13 Channel Aftertouch",
-    "Chan 14 Channel Aftertouch",
-    "Chan 15 Channel Aftertouch",
-    "Chan 16 Channel Aftertouch",
-    "Chan 1 Pitch Bend Change",
-    "Chan 2 Pitch Bend Change",
-    "Chan 3 Pitch Bend Change",
-    "Chan 4 Pitch Bend Change",
-    "Chan 5 Pitch Bend Change",
-    "Chan 6 Pitch Bend Change",
-    "Chan 7 Pitch Bend Change",
-    "Chan 8 Pitch Bend Change",
-    "Chan 9 Pitch Bend Change",
-    "Chan 10 Pitch Bend Change",
-    "Chan 11 Pitch Bend Change",
-    "Chan 12 Pitch Bend Change",
-    "Chan 13 Pitch Bend Change",
-    "Chan 14 Pitch Bend Change",
-    "Chan 15 Pitch Bend Change",
-    "Chan 16 Pitch Bend Change",
-    "System Exclusive",
-    "MIDI Time Code Qtr. Frame",
-    "Song Position Pointer",
-    "Song Select (Song #)",
-    "Undefined (Reserved)",
-    "Undefined (Reserved)",
-    "Tune request",
-    "End of SysEx (EOX)",
-    "Timing clock",
-    "Undefined (Reserved)",
-    "Start",
-    "Continue",
-    "Stop",
-    "Undefined (Reserved)",
-    "Active Sensing",
-    "System Reset",
-];
+    #[test]
+    fn it_follows_the_clocks_drifting_apart() {
+        let mut clock = MidiClock::default();
+        let start = Instant::now();
+        // the driver's clock runs 1% slow, so each second it falls another 10ms behind
+        let mut last = None;
+        for n in 0..60u64 {
+            let stamp = n * 990_000;
+            let hit = MidiInputDataRaw::from_message(
+                stamp,
+                &[153, 36, 90],
+                start + Duration::from_secs(n),
+            )
+            .unwrap();
+            clock.observe(&hit);
+            last = Some(hit);
+        }
+        // a running minimum would be stuck at the first offset, placing the last hit 590ms early.
+        // only recent offsets are kept, so it's off by at most the drift over the window
+        let last = last.unwrap();
+        let error = last.received - clock.struck_at(&last);
+        assert!(error <= Duration::from_millis(100), "{:?}", error);
+    }
+}
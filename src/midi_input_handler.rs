@@ -1,177 +1,222 @@
 /*
   Capture user input from midi. convert it into events.
 
-  This is stateful because it depends on setting up a connection to a midi input,
-  and flushing the internally stored events after the have been consumed via process().
-*/
+  This is stateful because it depends on setting up connections to midi inputs,
+  whose queued events are drained each frame via process().
+  The ports are rescanned every so often, so devices can be plugged in or unplugged while the app runs.
 
-use std::collections::HashSet;
+  While MIDI learn is active, the next pad that's hit is bound to an instrument instead of played.
+*/
 
-use macroquad::prelude::*;
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    consts::*, events::Events, midi::MidiInput, time::current_time_millis, voices::Instrument,
+    config::AppConfig,
+    consts::MIDI_PROFILES_DIR,
+    events::Events,
+    game::{MidiLearn, MidiPorts},
+    midi::{midi_port_names, port_device_name, MidiClock, MidiInput, MidiInputDataRaw},
+    midi_profile::{MidiProfile, MidiProfiles},
+    voices::Instrument,
 };
 
+/// how often to look for devices that were plugged in or unplugged
+const PORT_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// a connected device, with what's needed to turn its messages into hits
+struct MidiDevice {
+    midi_input: MidiInput,
+    /// each device's driver has its own clock
+    midi_clock: MidiClock,
+    /// maps the device's notes to instruments. It's chosen when the device connects.
+    midi_profile: MidiProfile,
+}
+
 pub struct MidiInputHandler {
-    midi_input: Option<MidiInput>,
+    devices: Vec<MidiDevice>,
+    /// kept so pads bound by MIDI learn can be saved to the device's profile
+    midi_profiles: MidiProfiles,
+    /// the selection the connected devices were chosen from
+    selected_ports: Option<Vec<String>>,
+    last_scan: Option<Instant>,
+    /// ports that couldn't be connected, so the error is only logged once
+    failed_ports: Vec<String>,
 }
 
 impl MidiInputHandler {
     pub fn new() -> Self {
-        let mut midi_input = MidiInput::new();
-        match midi_input {
-            Some(ref mut midi_input) => {
-                midi_input.connect();
-            }
-            None => log::warn!("warning: no midi input device found"),
+        let midi_profiles = MidiProfiles::load(
+            Path::new(MIDI_PROFILES_DIR),
+            AppConfig::user_midi_profiles_dir(),
+        );
+
+        Self {
+            devices: vec![],
+            midi_profiles,
+            selected_ports: None,
+            last_scan: None,
+            failed_ports: vec![],
         }
-
-        Self { midi_input }
     }
 
-    /// convert any user input from the last frame into Events
-    pub fn process(self: &mut Self) -> Vec<Events> {
-        let mut events: Vec<Events> = vec![];
-
-        // TODO(future): get the current clock time AND audio clock time at the start of a frame, and use that for all downstream calcs
-        let _now_ms = current_time_millis();
-        match &mut self.midi_input {
-            Some(midi_input) => {
-                let hits = get_midi_as_user_hits(midi_input);
-
-                // for each hit, calculate the processing delay and correct the clock time
-                for hit in &hits {
-                    // let processing_delay_ms = now_ms - hit.clock_tick as u128;
-                    //// TODO: needs work
-                    let processing_delay_ms = 0;
-                    events.push(Events::UserHit {
-                        instrument: hit.instrument,
-                        processing_delay: processing_delay_ms as f64 / 1000.,
-                    })
-                }
-
-                // let processing_delay = now - ; // is this better called "input latency"?
-                // let corrected_clock_time = current_clock_time - processing_delay;
+    /// convert any user input from the last frame into Events.
+    /// when learning, the first pad struck is bound to the instrument being learned, and learning finishes.
+    pub fn process(
+        self: &mut Self,
+        midi_ports: &mut MidiPorts,
+        midi_learn: &mut Option<MidiLearn>,
+    ) -> Vec<Events> {
+        // the audio clock is read straight after this, so the delay since each hit is measured from now
+        let now = Instant::now();
+        let is_scan_due = self
+            .last_scan
+            .map_or(true, |last| now.duration_since(last) >= PORT_SCAN_INTERVAL);
+        if is_scan_due || self.selected_ports != midi_ports.selected {
+            self.scan_ports(midi_ports);
+            self.last_scan = Some(now);
+        }
 
-                midi_input.flush();
+        let mut hits = vec![];
+        for device in &mut self.devices {
+            let mut pressed = device.midi_input.get_pressed_buttons();
+            if let Some(MidiLearn {
+                instrument: Some(instrument),
+                replace,
+            }) = *midi_learn
+            {
+                if !pressed.is_empty() {
+                    let learned = pressed.remove(0);
+                    learn(
+                        &mut self.midi_profiles,
+                        &mut device.midi_profile,
+                        learned.note_number,
+                        instrument,
+                        replace,
+                    );
+                    *midi_learn = None;
+                }
             }
-            None => {}
-        };
 
-        events
+            let device_hits = get_midi_hits(&pressed, &device.midi_profile);
+            for (_, midi) in &device_hits {
+                device.midi_clock.observe(midi);
+            }
+            // calculate when each pad was struck, so the clock time can be corrected
+            for (instrument, midi) in &device_hits {
+                hits.push((*instrument, device.midi_clock.struck_at(midi)));
+            }
+        }
+        // hits from several devices are played in the order they were struck
+        hits.sort_by_key(|(_, struck_at)| *struck_at);
+
+        hits.into_iter()
+            .map(|(instrument, struck_at)| Events::UserHit {
+                instrument,
+                processing_delay: now.saturating_duration_since(struck_at).as_secs_f64(),
+            })
+            .collect()
     }
-}
 
-struct InputConfigMidi {
-    kick: HashSet<u8>,
-    snare: HashSet<u8>,
-    closed_hi_hat: HashSet<u8>,
-    open_hi_hat: HashSet<u8>,
-    ride: HashSet<u8>,
-    crash: HashSet<u8>,
-    tom_1: HashSet<u8>,
-    tom_2: HashSet<u8>,
-    tom_3: HashSet<u8>,
-    pedal_hihat: HashSet<u8>,
-}
+    /// connects the selected ports that were plugged in, and drops the ones that were unplugged or deselected
+    fn scan_ports(&mut self, midi_ports: &mut MidiPorts) {
+        let available = midi_port_names();
+
+        self.devices.retain(|device| {
+            let name = device.midi_input.get_device_name();
+            let keep = available.iter().any(|n| n == name)
+                && midi_ports.is_selected(port_device_name(name));
+            if !keep {
+                log::info!("disconnected from midi device '{}'", name);
+            }
+            keep
+        });
+        self.failed_ports.retain(|name| available.contains(name));
+
+        for name in &available {
+            let is_connected = self
+                .devices
+                .iter()
+                .any(|device| device.midi_input.get_device_name() == name);
+            if is_connected || !midi_ports.is_selected(port_device_name(name)) {
+                continue;
+            }
+            match MidiInput::connect(name) {
+                Ok(midi_input) => {
+                    self.failed_ports.retain(|n| n != name);
+                    self.devices.push(MidiDevice {
+                        midi_input,
+                        midi_clock: MidiClock::default(),
+                        midi_profile: self.midi_profiles.profile_for_device(name),
+                    });
+                }
+                Err(e) => {
+                    if !self.failed_ports.contains(name) {
+                        log::error!(
+                            "error: unable to connect to midi device '{}' due to '{}'",
+                            name,
+                            e
+                        );
+                        self.failed_ports.push(name.clone());
+                    }
+                }
+            }
+        }
 
-impl InputConfigMidi {
-    pub fn get_note_numbers(self: &Self, ins: &Instrument) -> &HashSet<u8> {
-        match ins {
-            Instrument::ClosedHihat => &self.closed_hi_hat,
-            Instrument::Snare => &self.snare,
-            Instrument::Kick => &self.kick,
-            Instrument::OpenHihat => &self.open_hi_hat,
-            Instrument::Ride => &self.ride,
-            Instrument::Crash => &self.crash,
-            Instrument::Tom1 => &self.tom_1,
-            Instrument::Tom2 => &self.tom_2,
-            Instrument::Tom3 => &self.tom_3,
-            Instrument::PedalHiHat => &self.pedal_hihat,
+        if available.is_empty() && self.last_scan.is_none() {
+            log::warn!("warning: no midi input device found");
         }
+        self.selected_ports = midi_ports.selected.clone();
+        midi_ports.available = available
+            .iter()
+            .map(|name| port_device_name(name).to_string())
+            .collect();
+        midi_ports.connected = self
+            .devices
+            .iter()
+            .map(|device| port_device_name(device.midi_input.get_device_name()).to_string())
+            .collect();
     }
 }
 
-fn get_midi_as_user_hits(midi_input: &MidiInput) -> Vec<UserHit> {
-    let mut out: Vec<UserHit> = vec![];
-
-    // midi device: "MPK Mini Mk II"
-    let mpk_mini_mk_ii = InputConfigMidi {
-        closed_hi_hat: HashSet::from_iter(vec![44, 48]),
-        snare: HashSet::from_iter(vec![45, 49]),
-        kick: HashSet::from_iter(vec![46, 50]),
-        open_hi_hat: HashSet::from_iter(vec![47, 51]),
-        ride: HashSet::from_iter(vec![]),
-        crash: HashSet::from_iter(vec![]),
-        tom_1: HashSet::from_iter(vec![]),
-        tom_2: HashSet::from_iter(vec![]),
-        tom_3: HashSet::from_iter(vec![]),
-        pedal_hihat: HashSet::from_iter(vec![]),
-    };
-
-    // https://support.roland.com/hc/en-us/articles/360005173411-TD-17-Default-Factory-MIDI-Note-Map
-    let td17 = InputConfigMidi {
-        closed_hi_hat: HashSet::from_iter(vec![42, 22]),
-        snare: HashSet::from_iter(vec![38, 40, 37]),
-        kick: HashSet::from_iter(vec![36]),
-        open_hi_hat: HashSet::from_iter(vec![46, 26]),
-        ride: HashSet::from_iter(vec![51, 53, 59]),
-        crash: HashSet::from_iter(vec![49, 55, 57, 52]),
-        tom_1: HashSet::from_iter(vec![50, 48]),
-        tom_2: HashSet::from_iter(vec![47, 45]),
-        tom_3: HashSet::from_iter(vec![58, 43]),
-        pedal_hihat: HashSet::from_iter(vec![44]),
-    };
-
-    // https://support.roland.com/hc/en-us/articles/4407474950811-TD-27-Default-MIDI-Note-Map
-    let td27 = InputConfigMidi {
-        closed_hi_hat: HashSet::from_iter(vec![42, 22]),
-        snare: HashSet::from_iter(vec![38, 40, 37]),
-        kick: HashSet::from_iter(vec![36]),
-        open_hi_hat: HashSet::from_iter(vec![46, 26]),
-        ride: HashSet::from_iter(vec![51, 53, 59]),
-        crash: HashSet::from_iter(vec![49, 55, 57, 52]),
-        tom_1: HashSet::from_iter(vec![50, 48]),
-        tom_2: HashSet::from_iter(vec![47, 45]),
-        tom_3: HashSet::from_iter(vec![58, 43]),
-        pedal_hihat: HashSet::from_iter(vec![44]),
-    };
-
-    let alesis_nitro = InputConfigMidi {
-        closed_hi_hat: HashSet::from_iter(vec![42]),
-        snare: HashSet::from_iter(vec![38]),
-        kick: HashSet::from_iter(vec![36]),
-        open_hi_hat: HashSet::from_iter(vec![46, 23]),
-        ride: HashSet::from_iter(vec![]),
-        crash: HashSet::from_iter(vec![]),
-        tom_1: HashSet::from_iter(vec![]),
-        tom_2: HashSet::from_iter(vec![]),
-        tom_3: HashSet::from_iter(vec![]),
-        pedal_hihat: HashSet::from_iter(vec![]),
-    };
-
-    let ic_midi = match midi_input.get_device_name() {
-        s if s == "MPK Mini Mk II" => mpk_mini_mk_ii,
-        s if s.contains("TD-17") => td17,
-        s if s.contains("TD-27") => td27,
-        s if s.contains("Nitro") => alesis_nitro,
-        _ => {
-            log::warn!("warning: unknown midi device, using default of 'td27'");
-            td27
-        }
-    };
-
-    let pressed_midi = midi_input.get_pressed_buttons();
+/// binds a note to an instrument, and saves it to the device's profile so it's kept after a restart
+fn learn(
+    midi_profiles: &mut MidiProfiles,
+    midi_profile: &mut MidiProfile,
+    note_number: u8,
+    instrument: Instrument,
+    replace: bool,
+) {
+    midi_profile.learn(note_number, instrument, replace);
+    match midi_profiles.save_user_profile(midi_profile) {
+        Ok(path) => log::info!(
+            "midi note {} now plays {:?}, saved to {}",
+            note_number,
+            instrument,
+            path.display()
+        ),
+        Err(e) => log::error!(
+            "error: unable to save midi profile '{}' due to '{}'",
+            midi_profile.name,
+            e
+        ),
+    }
+}
 
-    // for each pressed_midi, check if it's in the ic_midi and then add to out as a proper UserHit if so
-    for midi in pressed_midi {
-        log::debug!("midi: {:?}", midi); // TODO: compare timestamps
-        let timestamp = midi.timestamp as f64;
-        for ins in ALL_INSTRUMENTS.iter() {
-            if ic_midi.get_note_numbers(ins).contains(&midi.note_number) {
-                out.push(UserHit::new(*ins, timestamp));
-            }
+/// the instrument for each pad that was struck since the last frame, in the order they were struck
+fn get_midi_hits(
+    pressed_midi: &[MidiInputDataRaw],
+    midi_profile: &MidiProfile,
+) -> Vec<(Instrument, MidiInputDataRaw)> {
+    let mut out = vec![];
+
+    // for each pressed_midi, check if it's in the profile and then add to out with its instrument if so
+    for &midi in pressed_midi {
+        log::debug!("midi: {:?}", midi);
+        for ins in midi_profile.get_instruments(midi.note_number) {
+            out.push((ins, midi));
         }
     }
 
@@ -0,0 +1,257 @@
+/*
+  MIDI mapping profiles, which map each MIDI note a device sends to the instrument it plays.
+*/
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{consts::ALL_INSTRUMENTS, midi::port_device_name, voices::Instrument};
+
+/// MidiProfile describes the notes sent by one kind of device, e.g. a model of electronic drum kit.
+/// Profiles are JSON files, so they can be edited to match a kit's own note map.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MidiProfile {
+    pub name: String,
+    /// the profile is used for devices whose name contains this text. Empty means it's never picked automatically.
+    #[serde(default)]
+    pub device_match: String,
+    /// the MIDI note numbers that play each instrument
+    #[serde(default)]
+    pub notes: HashMap<Instrument, Vec<u8>>,
+}
+
+impl MidiProfile {
+    pub fn new_from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        MidiProfile::new_from_reader(File::open(path)?)
+    }
+
+    fn new_from_reader(reader: impl Read) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn matches_device(&self, device_name: &str) -> bool {
+        !self.device_match.is_empty() && device_name.contains(&self.device_match)
+    }
+
+    /// binds a note to an instrument, either alongside its other notes or replacing them.
+    /// the note is taken away from any other instrument, so a pad only plays one instrument.
+    pub fn learn(&mut self, note_number: u8, instrument: Instrument, replace: bool) {
+        for notes in self.notes.values_mut() {
+            notes.retain(|n| *n != note_number);
+        }
+        let notes = self.notes.entry(instrument).or_default();
+        if replace {
+            notes.clear();
+        }
+        notes.push(note_number);
+    }
+
+    /// the instruments played by a note. Usually there's one, but a note can be mapped to several.
+    pub fn get_instruments(&self, note_number: u8) -> Vec<Instrument> {
+        ALL_INSTRUMENTS
+            .iter()
+            .filter(|ins| {
+                self.notes
+                    .get(ins)
+                    .is_some_and(|notes| notes.contains(&note_number))
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// MidiProfiles are the profiles available to choose from. They're read once, at startup.
+/// Profiles in the user's directory come first, so they take precedence over the bundled ones.
+pub struct MidiProfiles {
+    profiles: Vec<MidiProfile>,
+    user_dir: Option<PathBuf>,
+}
+
+impl MidiProfiles {
+    pub fn load(bundled_dir: &Path, user_dir: Option<PathBuf>) -> Self {
+        let mut profiles = vec![];
+        if let Some(user_dir) = &user_dir {
+            profiles.extend(read_profiles(user_dir));
+        }
+        profiles.extend(read_profiles(bundled_dir));
+        Self { profiles, user_dir }
+    }
+
+    /// finds the profile for a device. An unknown device gets a new profile starting from the General MIDI drum map.
+    /// it's only saved to the user's directory once it's edited, e.g. with MIDI learn.
+    pub fn profile_for_device(&self, port_name: &str) -> MidiProfile {
+        // the profile should still match once ALSA numbers the port differently
+        let device_name = port_device_name(port_name);
+        if let Some(profile) = self.profiles.iter().find(|p| p.matches_device(device_name)) {
+            log::info!(
+                "using midi profile '{}' for '{}'",
+                profile.name,
+                device_name
+            );
+            return profile.clone();
+        }
+
+        // the bundled General MIDI profile is the one that's never matched automatically
+        let template = self
+            .profiles
+            .iter()
+            .find(|p| p.device_match.is_empty())
+            .cloned()
+            .unwrap_or_default();
+        log::info!(
+            "unknown midi device '{}', using General MIDI notes",
+            device_name
+        );
+        MidiProfile {
+            name: device_name.to_string(),
+            device_match: device_name.to_string(),
+            ..template
+        }
+    }
+
+    /// saves a profile to the user's directory, where it takes precedence over a bundled profile of the same name.
+    /// returns the path it was saved to.
+    pub fn save_user_profile(&mut self, profile: &MidiProfile) -> Result<PathBuf, Box<dyn Error>> {
+        self.profiles.retain(|p| p.name != profile.name);
+        self.profiles.insert(0, profile.clone());
+
+        let user_dir = self
+            .user_dir
+            .as_ref()
+            .ok_or("no user directory for midi profiles")?;
+        let path = user_dir.join(profile_file_name(&profile.name));
+        profile.save(&path)?;
+        Ok(path)
+    }
+}
+
+/// reads every profile in a directory, skipping (and logging) any that can't be read
+fn read_profiles(dir: &Path) -> Vec<MidiProfile> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .filter_map(|path| match MidiProfile::new_from_file(path) {
+            Ok(profile) => Some(profile),
+            Err(e) => {
+                log::error!(
+                    "error: unable to read midi profile ({}) due to '{}'",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// a file name for a device's profile, keeping only characters that are safe in file names
+fn profile_file_name(device_name: &str) -> String {
+    let name: String = device_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("{}.json", name.trim_matches('-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        consts::MIDI_PROFILES_DIR,
+        midi_profile::{profile_file_name, MidiProfile, MidiProfiles},
+        voices::Instrument,
+    };
+
+    #[test]
+    fn it_matches_bundled_profiles_by_device_name() {
+        let profiles = MidiProfiles::load(Path::new(MIDI_PROFILES_DIR), None);
+        let profile = profiles.profile_for_device("TD-17 MIDI 1");
+        assert_eq!(profile.name, "Roland TD-17");
+        assert_eq!(profile.get_instruments(38), vec![Instrument::Snare]);
+        assert!(profile.get_instruments(100).is_empty());
+
+        // unknown devices fall back to General MIDI notes
+        let profile = profiles.profile_for_device("Mystery Kit 20:0");
+        assert_eq!(profile.name, "Mystery Kit");
+        assert_eq!(profile.get_instruments(36), vec![Instrument::Kick]);
+        assert!(profile.matches_device("Mystery Kit 24:0"));
+    }
+
+    #[test]
+    fn it_saves_a_new_profile_once_it_is_edited() {
+        let user_dir =
+            std::env::temp_dir().join(format!("macroix-midi-profiles-{}", std::process::id()));
+        let mut profiles = MidiProfiles::load(Path::new(MIDI_PROFILES_DIR), Some(user_dir.clone()));
+
+        // connecting a device, e.g. Linux's "Midi Through" port, doesn't leave a file behind
+        let mut profile = profiles.profile_for_device("Midi Through Port-0 14:0");
+        assert!(!user_dir.exists());
+
+        profile.learn(40, Instrument::Snare, false);
+        let path = profiles.save_user_profile(&profile).unwrap();
+        assert_eq!(path, user_dir.join("midi-through-port-0.json"));
+        assert!(path.exists());
+        std::fs::remove_dir_all(&user_dir).unwrap();
+    }
+
+    #[test]
+    fn it_reads_a_profile() {
+        let json =
+            r#"{"name": "Pads", "device_match": "Pad", "notes": {"snare": [1, 2], "kick": [2]}}"#;
+        let profile = MidiProfile::new_from_reader(json.as_bytes()).unwrap();
+        assert!(profile.matches_device("My Pad Controller"));
+        assert!(!profile.matches_device("TD-27"));
+        assert_eq!(
+            profile.get_instruments(2),
+            vec![Instrument::Snare, Instrument::Kick]
+        );
+        assert_eq!(profile_file_name("TD-27 (USB) "), "td-27--usb.json");
+    }
+
+    #[test]
+    fn it_learns_a_pad() {
+        let json = r#"{"name": "Pads", "notes": {"snare": [1, 2], "kick": [3]}}"#;
+        let mut profile = MidiProfile::new_from_reader(json.as_bytes()).unwrap();
+
+        // adding keeps the instrument's other pads, but takes the pad from its old instrument
+        profile.learn(3, Instrument::Snare, false);
+        assert_eq!(profile.notes[&Instrument::Snare], vec![1, 2, 3]);
+        assert!(profile.get_instruments(3) == vec![Instrument::Snare]);
+
+        profile.learn(4, Instrument::Snare, true);
+        assert_eq!(profile.notes[&Instrument::Snare], vec![4]);
+        profile.learn(5, Instrument::Ride, true);
+        assert_eq!(profile.get_instruments(5), vec![Instrument::Ride]);
+    }
+}
@@ -0,0 +1,140 @@
+/*
+  Mixer levels for each instrument, the metronome, and the overall output.
+*/
+use std::collections::HashMap;
+
+use crate::{consts::ALL_INSTRUMENTS, voices::Instrument};
+
+/// Channel is the mixer settings for a single instrument.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Channel {
+    pub volume: f64,
+    pub muted: bool,
+    pub soloed: bool,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self {
+            volume: 1.,
+            muted: false,
+            soloed: false,
+        }
+    }
+}
+
+/// Mixer holds the volume of each instrument, the metronome, the backing track, the user's hits, and the overall output.
+/// Volumes are amplitudes from 0 (silent) to 1 (unchanged).
+///
+/// When any instrument is soloed, only soloed instruments are heard. The metronome, backing track and hit sounds are never affected by solo.
+#[derive(Debug, Clone)]
+pub struct Mixer {
+    channels: HashMap<Instrument, Channel>,
+    pub metronome_volume: f64,
+    pub backing_track_volume: f64,
+    /// sounds played when the user hits, separate from the target notes
+    pub hit_volume: f64,
+    pub master_volume: f64,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            channels: ALL_INSTRUMENTS
+                .iter()
+                .map(|ins| (*ins, Channel::default()))
+                .collect(),
+            metronome_volume: 1.,
+            backing_track_volume: 1.,
+            hit_volume: 1.,
+            master_volume: 1.,
+        }
+    }
+
+    pub fn get_channel(&self, ins: &Instrument) -> Channel {
+        self.channels.get(ins).copied().unwrap_or_default()
+    }
+
+    fn get_channel_mut(&mut self, ins: &Instrument) -> &mut Channel {
+        self.channels.entry(*ins).or_default()
+    }
+
+    pub fn set_volume(&mut self, ins: &Instrument, volume: f64) {
+        self.get_channel_mut(ins).volume = volume.clamp(0., 1.);
+    }
+
+    pub fn toggle_mute(&mut self, ins: &Instrument) {
+        let channel = self.get_channel_mut(ins);
+        channel.muted = !channel.muted;
+    }
+
+    pub fn toggle_solo(&mut self, ins: &Instrument) {
+        let channel = self.get_channel_mut(ins);
+        channel.soloed = !channel.soloed;
+    }
+
+    pub fn set_metronome_volume(&mut self, volume: f64) {
+        self.metronome_volume = volume.clamp(0., 1.);
+    }
+
+    pub fn set_backing_track_volume(&mut self, volume: f64) {
+        self.backing_track_volume = volume.clamp(0., 1.);
+    }
+
+    pub fn set_hit_volume(&mut self, volume: f64) {
+        self.hit_volume = volume.clamp(0., 1.);
+    }
+
+    pub fn set_master_volume(&mut self, volume: f64) {
+        self.master_volume = volume.clamp(0., 1.);
+    }
+
+    /// the volume an instrument is actually played at, after applying mute and solo
+    pub fn effective_volume(&self, ins: &Instrument) -> f64 {
+        let channel = self.get_channel(ins);
+        let any_soloed = self.channels.values().any(|c| c.soloed);
+        if channel.muted || (any_soloed && !channel.soloed) {
+            0.
+        } else {
+            channel.volume
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mixer::Mixer, voices::Instrument};
+
+    #[test]
+    fn it_mutes_an_instrument() {
+        let mut mixer = Mixer::new();
+        mixer.set_volume(&Instrument::Kick, 0.5);
+        assert_eq!(mixer.effective_volume(&Instrument::Kick), 0.5);
+
+        mixer.toggle_mute(&Instrument::Kick);
+        assert_eq!(mixer.effective_volume(&Instrument::Kick), 0.);
+        assert_eq!(mixer.effective_volume(&Instrument::Snare), 1.);
+
+        // unmuting restores the previous volume
+        mixer.toggle_mute(&Instrument::Kick);
+        assert_eq!(mixer.effective_volume(&Instrument::Kick), 0.5);
+    }
+
+    #[test]
+    fn it_only_plays_soloed_instruments() {
+        let mut mixer = Mixer::new();
+        mixer.toggle_solo(&Instrument::Snare);
+        mixer.toggle_solo(&Instrument::ClosedHihat);
+        assert_eq!(mixer.effective_volume(&Instrument::Snare), 1.);
+        assert_eq!(mixer.effective_volume(&Instrument::ClosedHihat), 1.);
+        assert_eq!(mixer.effective_volume(&Instrument::Kick), 0.);
+
+        // mute wins over solo
+        mixer.toggle_mute(&Instrument::Snare);
+        assert_eq!(mixer.effective_volume(&Instrument::Snare), 0.);
+
+        mixer.toggle_solo(&Instrument::Snare);
+        mixer.toggle_solo(&Instrument::ClosedHihat);
+        assert_eq!(mixer.effective_volume(&Instrument::Kick), 1.);
+    }
+}
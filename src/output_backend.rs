@@ -0,0 +1,204 @@
+/*
+  The audio output: a sound card when one is available, and silence otherwise.
+*/
+use std::{error::Error, ops::RangeInclusive};
+
+#[cfg(not(target_arch = "wasm32"))]
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    BufferSize, Device, SupportedBufferSize,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use kira::manager::backend::cpal::{CpalBackend, CpalBackendSettings};
+use kira::manager::backend::{mock::MockBackendSettings, Backend, Renderer};
+
+use crate::headless_backend::HeadlessBackend;
+
+/// sample rate used when there's no sound card. Nothing is heard, so it only needs to be fine enough to keep time.
+const SILENT_SAMPLE_RATE: u32 = 1000;
+
+/// OutputSettings chooses the sound card and how much audio it buffers. They're saved in `AppConfig`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputSettings {
+    /// empty means the system's default output device
+    pub device_name: String,
+    /// frames buffered by the device. Smaller buffers lower the latency, but may crackle. 0 means the device's default.
+    pub buffer_size_frames: u32,
+}
+
+/// OutputDevices are the output devices that can be chosen, and the buffer sizes each supports
+#[derive(Debug, Clone, Default)]
+pub struct OutputDevices {
+    /// the system's default output device, if there is one
+    pub default_name: Option<String>,
+    pub devices: Vec<(String, Option<RangeInclusive<u32>>)>,
+}
+
+impl OutputDevices {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn scan() -> Self {
+        let host = cpal::default_host();
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+        let devices = match host.output_devices() {
+            Ok(devices) => devices
+                .filter_map(|device| Some((device.name().ok()?, supported_buffer_sizes(&device))))
+                .collect(),
+            Err(_) => vec![],
+        };
+        Self {
+            default_name,
+            devices,
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn scan() -> Self {
+        Self::default()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.devices.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// frames the device can buffer, or None if it doesn't say. An empty name is the default device.
+    pub fn buffer_sizes(&self, device_name: &str) -> Option<RangeInclusive<u32>> {
+        let name = match device_name {
+            "" => self.default_name.as_deref()?,
+            name => name,
+        };
+        self.devices
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, sizes)| sizes.clone())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn supported_buffer_sizes(device: &Device) -> Option<RangeInclusive<u32>> {
+    match device.default_output_config().ok()?.buffer_size() {
+        SupportedBufferSize::Range { min, max } => Some(*min..=*max),
+        SupportedBufferSize::Unknown => None,
+    }
+}
+
+/// the buffer size to ask the device for. Kira opens the stream on its own thread, where a size the device
+/// doesn't support would panic, so those fall back to the default.
+#[cfg(not(target_arch = "wasm32"))]
+fn buffer_size(frames: u32, supported: Option<RangeInclusive<u32>>) -> BufferSize {
+    match (frames, supported) {
+        (0, _) => BufferSize::Default,
+        (frames, Some(supported)) if supported.contains(&frames) => BufferSize::Fixed(frames),
+        (frames, supported) => {
+            log::error!(
+                "buffer size of {} frames isn't supported by the output device ({:?}), using the default",
+                frames,
+                supported
+            );
+            BufferSize::Default
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn find_output_device(name: &str) -> Option<Device> {
+    cpal::default_host()
+        .output_devices()
+        .ok()?
+        .find(|device| device.name().is_ok_and(|n| n == name))
+}
+
+/// OutputBackend plays to a sound card. If none can be opened, it keeps time silently instead of crashing,
+/// rendering whenever `Audio::advance` is called.
+pub enum OutputBackend {
+    #[cfg(not(target_arch = "wasm32"))]
+    Device(CpalBackend),
+    Silent(Box<HeadlessBackend>),
+}
+
+impl OutputBackend {
+    pub fn is_silent(&self) -> bool {
+        matches!(self, OutputBackend::Silent(_))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn setup_device(settings: &OutputSettings) -> Result<(Self, u32), Box<dyn Error>> {
+        let device = if settings.device_name.is_empty() {
+            None
+        } else {
+            let device = find_output_device(&settings.device_name);
+            if device.is_none() {
+                log::error!(
+                    "output device '{}' not found, using the default device",
+                    settings.device_name
+                );
+            }
+            device
+        };
+        let device = match device {
+            Some(device) => device,
+            None => cpal::default_host()
+                .default_output_device()
+                .ok_or("no output device available")?,
+        };
+        let buffer_size = buffer_size(settings.buffer_size_frames, supported_buffer_sizes(&device));
+        let (backend, sample_rate) = CpalBackend::setup(CpalBackendSettings {
+            device: Some(device),
+            buffer_size,
+        })?;
+        Ok((OutputBackend::Device(backend), sample_rate))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn setup_device(_settings: &OutputSettings) -> Result<(Self, u32), Box<dyn Error>> {
+        Err("audio output isn't supported on the web yet".into())
+    }
+}
+
+impl Backend for OutputBackend {
+    type Settings = OutputSettings;
+
+    type Error = Box<dyn Error>;
+
+    fn setup(settings: Self::Settings) -> Result<(Self, u32), Self::Error> {
+        match OutputBackend::setup_device(&settings) {
+            Ok(out) => Ok(out),
+            Err(e) => {
+                log::error!(
+                    "unable to open audio output, continuing without sound: {}",
+                    e
+                );
+                let (backend, sample_rate) = HeadlessBackend::setup(MockBackendSettings {
+                    sample_rate: SILENT_SAMPLE_RATE,
+                })
+                .map_err(|_| "unable to set up silent audio output")?;
+                Ok((OutputBackend::Silent(Box::new(backend)), sample_rate))
+            }
+        }
+    }
+
+    fn start(&mut self, renderer: Renderer) -> Result<(), Self::Error> {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            OutputBackend::Device(backend) => Ok(backend.start(renderer)?),
+            OutputBackend::Silent(backend) => backend
+                .start(renderer)
+                .map_err(|_| "unable to start silent audio output".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::BufferSize;
+
+    use crate::output_backend::buffer_size;
+
+    #[test]
+    fn it_only_fixes_buffer_sizes_the_device_supports() {
+        assert_eq!(buffer_size(0, Some(64..=4096)), BufferSize::Default);
+        assert_eq!(buffer_size(256, Some(64..=4096)), BufferSize::Fixed(256));
+        assert_eq!(buffer_size(32, Some(64..=4096)), BufferSize::Default);
+        assert_eq!(buffer_size(8192, Some(64..=4096)), BufferSize::Default);
+        assert_eq!(buffer_size(256, None), BufferSize::Default);
+    }
+}
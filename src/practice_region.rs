@@ -0,0 +1,92 @@
+/*
+  Practice regions, which repeat a range of steps instead of the whole loop.
+*/
+use crate::voices::{Dynamic, Voices};
+
+/// PracticeRegion is a range of the loop to repeat, e.g. a tricky fill at the end of a long groove.
+/// The rest of the arrangement stays loaded, so the region can be changed or cleared at any time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PracticeRegion {
+    pub start_beat: f64,
+    /// the first beat after the region
+    pub end_beat: f64,
+}
+
+impl PracticeRegion {
+    /// the region from the start of one grid step to the end of another, where each beat has `subdivision` steps.
+    /// the steps can be given in either order.
+    pub fn from_steps(first_step: u32, last_step: u32, subdivision: u32) -> Self {
+        let steps_per_beat = subdivision.max(1) as f64;
+        Self {
+            start_beat: first_step.min(last_step) as f64 / steps_per_beat,
+            end_beat: (first_step.max(last_step) + 1) as f64 / steps_per_beat,
+        }
+    }
+
+    pub fn first_step(&self, subdivision: u32) -> u32 {
+        (self.start_beat * subdivision as f64).round() as u32
+    }
+
+    pub fn last_step(&self, subdivision: u32) -> u32 {
+        ((self.end_beat * subdivision as f64).round() as u32).saturating_sub(1)
+    }
+
+    pub fn length_beats(&self) -> f64 {
+        self.end_beat - self.start_beat
+    }
+
+    pub fn contains(&self, beat: f64) -> bool {
+        beat >= self.start_beat && beat < self.end_beat
+    }
+
+    /// keeps the voices' notes inside the region, moving them so the region starts at beat 0
+    pub fn crop_voices(&self, voices: &Voices) -> Voices {
+        voices.crop(self.start_beat, self.end_beat)
+    }
+
+    /// like `crop_voices`, for a single sound's notes
+    pub fn crop_notes(&self, notes: &[(f64, Dynamic)]) -> Vec<(f64, Dynamic)> {
+        notes
+            .iter()
+            .filter(|(beat, _)| self.contains(*beat))
+            .map(|(beat, dynamic)| (beat - self.start_beat, *dynamic))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{practice_region::PracticeRegion, voices::Dynamic};
+
+    #[test]
+    fn it_selects_a_range_of_steps() {
+        // 4 steps per beat, so the 2nd beat is steps 4 to 7
+        let region = PracticeRegion::from_steps(7, 4, 4);
+        assert_eq!(region.start_beat, 1.);
+        assert_eq!(region.end_beat, 2.);
+        assert_eq!((region.first_step(4), region.last_step(4)), (4, 7));
+
+        // picking the same step twice selects just that step
+        let region = PracticeRegion::from_steps(3, 3, 4);
+        assert_eq!(region.length_beats(), 0.25);
+        assert_eq!((region.first_step(4), region.last_step(4)), (3, 3));
+    }
+
+    #[test]
+    fn it_crops_notes_to_the_region() {
+        let region = PracticeRegion {
+            start_beat: 2.,
+            end_beat: 4.,
+        };
+        let notes = vec![
+            (0., Dynamic::Normal),
+            (2., Dynamic::Accent),
+            (3.5, Dynamic::Ghost),
+            (4., Dynamic::Normal),
+        ];
+        assert_eq!(
+            region.crop_notes(&notes),
+            vec![(0., Dynamic::Accent), (1.5, Dynamic::Ghost)]
+        );
+    }
+}
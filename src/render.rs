@@ -0,0 +1,119 @@
+/*
+  Offline rendering of loops to WAV files, e.g. for sharing exercises or checking a kit.
+*/
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use kira::Frame;
+
+use crate::{
+    audio::{Audio, AudioBackend},
+    voices::Voices,
+};
+
+pub const RENDER_SAMPLE_RATE: u32 = 44100;
+
+const WAV_CHANNELS: u16 = 2;
+const WAV_BITS_PER_SAMPLE: u16 = 16;
+
+/// writes stereo frames as a 16-bit PCM WAV file. Samples outside -1 to 1 are clipped.
+pub fn write_wav(mut writer: impl Write, frames: &[Frame], sample_rate: u32) -> io::Result<()> {
+    let block_align = WAV_CHANNELS * WAV_BITS_PER_SAMPLE / 8;
+    let data_len = frames.len() as u32 * block_align as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&WAV_CHANNELS.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&WAV_BITS_PER_SAMPLE.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for frame in frames {
+        for sample in [frame.left, frame.right] {
+            let sample = (sample.clamp(-1., 1.) * i16::MAX as f32) as i16;
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+/// renders repetitions of the loop to a WAV file on another thread, so the app keeps running meanwhile.
+/// failures are logged, since there's nothing left to return them to.
+pub fn export_wav(
+    audio: &Audio<impl AudioBackend>,
+    voices: &Voices,
+    repetitions: u32,
+    path: PathBuf,
+) {
+    let (tx, rx) = mpsc::channel();
+    let mut offline = audio.offline_copy(RENDER_SAMPLE_RATE, tx);
+    let voices = voices.clone();
+    std::thread::spawn(move || {
+        // the receiver is kept so that loop messages can still be sent
+        let _rx = rx;
+        let result = offline
+            .render_loops(&voices, repetitions)
+            .and_then(|frames| save_wav(&path, &frames));
+        match result {
+            Ok(()) => log::info!("exported the loop to {}", path.display()),
+            Err(e) => log::error!(
+                "error: unable to export the loop to {} due to '{}'",
+                path.display(),
+                e
+            ),
+        }
+    });
+}
+
+/// writes the frames to a new file. A file that can't be written completely is removed.
+fn save_wav(path: &Path, frames: &[Frame]) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = File::create(path)?;
+    if let Err(e) = write_wav(BufWriter::new(file), frames, RENDER_SAMPLE_RATE) {
+        let _ = std::fs::remove_file(path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kira::Frame;
+
+    use crate::render::write_wav;
+
+    #[test]
+    fn it_writes_a_wav_file() {
+        let frames = vec![Frame::new(1., -1.), Frame::new(2., 0.)];
+        let mut out = vec![];
+        write_wav(&mut out, &frames, 8000).unwrap();
+
+        assert_eq!(out.len(), 44 + 2 * 4);
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(&out[8..12], b"WAVE");
+        assert_eq!(u32::from_le_bytes(out[24..28].try_into().unwrap()), 8000);
+        assert_eq!(u32::from_le_bytes(out[40..44].try_into().unwrap()), 8);
+
+        let samples: Vec<i16> = out[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        // out of range samples are clipped
+        assert_eq!(samples, vec![i16::MAX, -i16::MAX, i16::MAX, 0]);
+    }
+}
@@ -8,8 +8,9 @@ use std::{collections::HashMap, ops::Add, vec};
 
 use crate::{
     consts::UserHit,
-    consts::{ALL_INSTRUMENTS, BEATS_PER_LOOP},
-    voices::{Instrument, Voices},
+    consts::ALL_INSTRUMENTS,
+    metronome::GapClick,
+    voices::{Instrument, TimeSignature, Voices},
 };
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -26,13 +27,15 @@ pub enum Accuracy {
 // - Can't hash floating point numbers out of the gate
 
 // TODO: Can I use cvars to tweak these vals at runtime?
-pub const CORRECT_MARGIN: f64 = 0.151; // TODO: hacky fix 0.15 -> 0.151 due to floating point comparison. let's try Decimal later
-pub const MISS_MARGIN: f64 = 0.3;
+// Margins are measured in beats (quarter notes)
+pub const CORRECT_MARGIN: f64 = 0.0755; // TODO: hacky fix 0.075 -> 0.0755 due to floating point comparison. let's try Decimal later
+pub const MISS_MARGIN: f64 = 0.15;
 
 /// returns a tuple of (accuracy rating, a bool of whether not this measurement is wrapping around to the _next_ loop)
 pub fn compute_accuracy_of_single_hit(
     user_beat_with_latency: f64,
     desired_hits: &Vec<f64>,
+    beats_per_loop: f64,
     // correct_margin,
     // miss_margin,
 ) -> (Accuracy, bool) {
@@ -57,7 +60,7 @@ pub fn compute_accuracy_of_single_hit(
     // handle end of loop wrap-around case
     let mut is_next_loop = false;
     if desired_hits.contains(&0.) {
-        let desired = 0. + BEATS_PER_LOOP;
+        let desired = 0. + beats_per_loop;
         // if there's no target_beat yet, set it to the first desired hit
         match target_beat {
             None => {
@@ -190,11 +193,12 @@ impl LastLoopSummary {
 pub fn get_user_hit_timings_by_instrument(
     user_hits: &Vec<UserHit>,
     instrument: Instrument,
+    beats_per_loop: f64,
 ) -> Vec<f64> {
     user_hits
         .iter()
         .filter(|hit| hit.instrument == instrument)
-        .map(|hit| hit.beat())
+        .map(|hit| hit.beat(beats_per_loop))
         .collect::<Vec<f64>>()
 }
 
@@ -205,6 +209,7 @@ pub fn compute_loop_performance_for_voice(
     user_hits: &Vec<f64>,
     desired_hits: &Vec<f64>,
     loop_current_beat: f64,
+    beats_per_loop: f64,
     // TODO: consider audio_latency
 ) -> Vec<Accuracy> {
     let mut out = Vec::new();
@@ -219,7 +224,8 @@ pub fn compute_loop_performance_for_voice(
         // find the first user hit that a non-miss
         let mut was_miss = true;
         for user_hit in user_hits {
-            let (acc, _) = compute_accuracy_of_single_hit(*user_hit, &vec![*desired_hit]);
+            let (acc, _) =
+                compute_accuracy_of_single_hit(*user_hit, &vec![*desired_hit], beats_per_loop);
             if acc != Accuracy::Miss {
                 was_miss = false;
                 out.push(acc);
@@ -238,12 +244,14 @@ pub fn compute_last_loop_summary(
     user_hits: &Vec<UserHit>,
     desired_hits: &Voices,
     audio_latency: f64,
+    beats_per_loop: f64,
 ) -> LastLoopSummary {
     let mut out = LastLoopSummary::new();
 
     for (_, instrument) in ALL_INSTRUMENTS.iter().enumerate() {
         // // get accuracy of hihat
-        let user_timings = get_user_hit_timings_by_instrument(user_hits, *instrument);
+        let user_timings =
+            get_user_hit_timings_by_instrument(user_hits, *instrument, beats_per_loop);
         let desired_timings = desired_hits.get_instrument_beats(instrument);
 
         // let mut accuracies = vec![];
@@ -256,8 +264,12 @@ pub fn compute_last_loop_summary(
         //     accuracies.push(acc);
         // }
 
-        let accuracies =
-            compute_loop_performance_for_voice(&user_timings, desired_timings, BEATS_PER_LOOP);
+        let accuracies = compute_loop_performance_for_voice(
+            &user_timings,
+            &desired_timings,
+            beats_per_loop,
+            beats_per_loop,
+        );
 
         out.set_score_tracker(instrument, ScoreTracker { accuracies });
     }
@@ -265,17 +277,83 @@ pub fn compute_last_loop_summary(
     out
 }
 
+/// DriftSummary is the average timing offset of the user's hits, in beats, split by whether
+/// the gap click was audible or silent. Positive values are late, negative are early.
+#[derive(Debug, Default, PartialEq)]
+pub struct DriftSummary {
+    pub audible: Option<f64>,
+    pub silent: Option<f64>,
+}
+
+/// signed distance from a hit to the nearest desired hit (including across the loop boundary),
+/// or None if it's a miss
+fn offset_from_nearest_hit(
+    user_beat_with_latency: f64,
+    desired_hits: &[f64],
+    beats_per_loop: f64,
+) -> Option<f64> {
+    desired_hits
+        .iter()
+        .flat_map(|d| [*d - beats_per_loop, *d, *d + beats_per_loop])
+        .map(|d| user_beat_with_latency - d)
+        .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+        .filter(|offset| offset.abs() <= MISS_MARGIN)
+}
+
+/// compares timing during audible and silent bars, to show how far the user drifts without a click
+pub fn compute_drift(
+    user_hits: &Vec<UserHit>,
+    desired_hits: &Voices,
+    audio_latency: f64,
+    beats_per_loop: f64,
+    gap_click: &GapClick,
+    time_signature: &TimeSignature,
+) -> DriftSummary {
+    let mut audible = vec![];
+    let mut silent = vec![];
+    for hit in user_hits {
+        let Some(offset) = offset_from_nearest_hit(
+            hit.beat(beats_per_loop) + audio_latency,
+            &desired_hits.get_instrument_beats(&hit.instrument),
+            beats_per_loop,
+        ) else {
+            continue;
+        };
+
+        // use the bar of the note the user was aiming for, so early hits on a downbeat count towards that bar
+        let target_beat = hit.clock_tick + audio_latency - offset;
+        if gap_click.is_audible(target_beat, time_signature) {
+            audible.push(offset);
+        } else {
+            silent.push(offset);
+        }
+    }
+
+    let mean = |offsets: Vec<f64>| {
+        if offsets.is_empty() {
+            None
+        } else {
+            Some(offsets.iter().sum::<f64>() / offsets.len() as f64)
+        }
+    };
+    DriftSummary {
+        audible: mean(audible),
+        silent: mean(silent),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::f64::EPSILON;
 
     use crate::{
-        consts::{UserHit, BEATS_PER_LOOP},
+        consts::{UserHit, DEFAULT_BEATS_PER_LOOP},
+        metronome::GapClick,
         score::{
-            compute_accuracy_of_single_hit, compute_last_loop_summary, Accuracy, CORRECT_MARGIN,
-            MISS_MARGIN,
+            compute_accuracy_of_single_hit, compute_drift, compute_last_loop_summary, Accuracy,
+            CORRECT_MARGIN, MISS_MARGIN,
         },
-        voices::{Instrument, Voices},
+        voices::{Instrument, TimeSignature, Voices},
     };
 
     use super::compute_loop_performance_for_voice;
@@ -287,7 +365,12 @@ mod tests {
     #[test]
     fn it_computes_accuracy_against_one_note() {
         let compute_accuracy_legacy = |user_beat_with_latency: f64, desired_hits: &Vec<f64>| {
-            compute_accuracy_of_single_hit(user_beat_with_latency, desired_hits).0
+            compute_accuracy_of_single_hit(
+                user_beat_with_latency,
+                desired_hits,
+                DEFAULT_BEATS_PER_LOOP,
+            )
+            .0
         };
 
         // exactly correct
@@ -329,7 +412,12 @@ mod tests {
     #[test]
     fn it_computes_accuracy_against_correct_target_note_from_many() {
         let compute_accuracy_legacy = |user_beat_with_latency: f64, desired_hits: &Vec<f64>| {
-            compute_accuracy_of_single_hit(user_beat_with_latency, desired_hits).0
+            compute_accuracy_of_single_hit(
+                user_beat_with_latency,
+                desired_hits,
+                DEFAULT_BEATS_PER_LOOP,
+            )
+            .0
         };
 
         // should check if it's closer to the nearest note: 0.0, not 1.0
@@ -337,27 +425,36 @@ mod tests {
         assert_eq!(result, Accuracy::Correct);
 
         // handle wrap-around case
-        let result = compute_accuracy_legacy(BEATS_PER_LOOP - CORRECT_MARGIN, &vec![0.0, 1.0]);
+        let result =
+            compute_accuracy_legacy(DEFAULT_BEATS_PER_LOOP - CORRECT_MARGIN, &vec![0.0, 1.0]);
         assert_eq!(result, Accuracy::Correct);
 
         let result = compute_accuracy_legacy(
-            BEATS_PER_LOOP - CORRECT_MARGIN - EPSILON * 5.,
+            DEFAULT_BEATS_PER_LOOP - CORRECT_MARGIN - EPSILON * 5.,
             &vec![0.0, 1.0],
         );
         assert_eq!(result, Accuracy::Early);
 
-        let result = compute_accuracy_legacy(BEATS_PER_LOOP - MISS_MARGIN, &vec![0.0, 1.0]);
+        let result = compute_accuracy_legacy(
+            DEFAULT_BEATS_PER_LOOP - MISS_MARGIN - EPSILON,
+            &vec![0.0, 1.0],
+        );
         assert_eq!(result, Accuracy::Miss);
     }
 
     #[test]
     fn it_computes_accuracy_considering_is_next_loop() {
-        let result = compute_accuracy_of_single_hit(BEATS_PER_LOOP - CORRECT_MARGIN, &vec![0.0]);
+        let result = compute_accuracy_of_single_hit(
+            DEFAULT_BEATS_PER_LOOP - CORRECT_MARGIN,
+            &vec![0.0],
+            DEFAULT_BEATS_PER_LOOP,
+        );
         assert_eq!(result, (Accuracy::Correct, true));
 
         let result = compute_accuracy_of_single_hit(
-            BEATS_PER_LOOP - CORRECT_MARGIN - EPSILON * 5.,
+            DEFAULT_BEATS_PER_LOOP - CORRECT_MARGIN - EPSILON * 5.,
             &vec![0.0],
+            DEFAULT_BEATS_PER_LOOP,
         );
         assert_eq!(result, (Accuracy::Early, true));
     }
@@ -372,7 +469,8 @@ mod tests {
         let mut desired_hits = Voices::new();
         desired_hits.toggle_beat(Instrument::Kick, 0.0);
 
-        let result = compute_last_loop_summary(&user_hits, &desired_hits, 0.0);
+        let result =
+            compute_last_loop_summary(&user_hits, &desired_hits, 0.0, DEFAULT_BEATS_PER_LOOP);
         assert_eq!(
             result.get_score_tracker(&Instrument::Kick).accuracies,
             vec![Accuracy::Correct],
@@ -381,11 +479,12 @@ mod tests {
 
     #[test]
     fn it_computes_last_loop_summary_for_incorrect_user_hits() {
-        let user_hits = vec![UserHit::new(Instrument::Kick, 0.5)];
+        let user_hits = vec![UserHit::new(Instrument::Kick, 0.25)];
         let mut desired_hits = Voices::new();
         desired_hits.toggle_beat(Instrument::Kick, 0.0);
 
-        let result = compute_last_loop_summary(&user_hits, &desired_hits, 0.0);
+        let result =
+            compute_last_loop_summary(&user_hits, &desired_hits, 0.0, DEFAULT_BEATS_PER_LOOP);
         assert_eq!(
             result.get_score_tracker(&Instrument::Kick).accuracies,
             vec![Accuracy::Miss],
@@ -394,14 +493,76 @@ mod tests {
 
     #[test]
     fn it_computes_loop_performance_for_voice() {
-        let user_hits = vec![0.5, 0.6, 0.8];
-        let desired_hits = vec![0.0, 0.5, 1.0];
+        let user_hits = vec![0.25, 0.3, 0.4];
+        let desired_hits = vec![0.0, 0.25, 0.5];
         let loop_current_beat = 4.;
-        let result =
-            compute_loop_performance_for_voice(&user_hits, &desired_hits, loop_current_beat);
+        let result = compute_loop_performance_for_voice(
+            &user_hits,
+            &desired_hits,
+            loop_current_beat,
+            DEFAULT_BEATS_PER_LOOP,
+        );
         assert_eq!(
             result,
             vec![Accuracy::Miss, Accuracy::Correct, Accuracy::Early]
         );
     }
+
+    #[test]
+    fn it_computes_accuracy_wrapping_around_a_variable_length_loop() {
+        // a 12 beat loop wraps back to beat 0 after beat 12, not 16
+        let result = compute_accuracy_of_single_hit(12. - CORRECT_MARGIN, &vec![0.0, 8.0], 12.);
+        assert_eq!(result, (Accuracy::Correct, true));
+
+        let result = compute_accuracy_of_single_hit(16. - CORRECT_MARGIN, &vec![0.0, 8.0], 32.);
+        assert_eq!(result, (Accuracy::Miss, false));
+    }
+
+    #[test]
+    fn it_computes_last_loop_summary_for_a_longer_loop() {
+        let user_hits = vec![
+            UserHit::new(Instrument::Snare, 4.0),
+            UserHit::new(Instrument::Snare, 28.0),
+        ];
+        let mut desired_hits = Voices::new();
+        desired_hits.toggle_beat(Instrument::Snare, 4.0);
+        desired_hits.toggle_beat(Instrument::Snare, 28.0);
+
+        let result = compute_last_loop_summary(&user_hits, &desired_hits, 0.0, 32.);
+        assert_eq!(
+            result.get_score_tracker(&Instrument::Snare).accuracies,
+            vec![Accuracy::Correct, Accuracy::Correct],
+        );
+    }
+
+    #[test]
+    fn it_computes_drift_during_silent_bars() {
+        let mut desired_hits = Voices::new();
+        for beat in [0., 1., 2., 3.] {
+            desired_hits.toggle_beat(Instrument::Snare, beat);
+        }
+        let gap_click = GapClick {
+            enabled: true,
+            audible_bars: 1,
+            silent_bars: 1,
+            ..GapClick::default()
+        };
+
+        // on time while the click is heard, then rushing more and more once it's silent.
+        // the early hit on beat 8 belongs to the next (audible) bar, and the miss is ignored.
+        let user_hits = [0., 1., 2., 3., 3.98, 4.94, 5.92, 6.9, 7.96, 9.5]
+            .iter()
+            .map(|beat| UserHit::new(Instrument::Snare, *beat))
+            .collect();
+        let drift = compute_drift(
+            &user_hits,
+            &desired_hits,
+            0.,
+            DEFAULT_BEATS_PER_LOOP,
+            &gap_click,
+            &TimeSignature::default(),
+        );
+        assert!((drift.audible.unwrap() - -0.008).abs() < 1e-9);
+        assert!((drift.silent.unwrap() - -0.065).abs() < 1e-9);
+    }
 }
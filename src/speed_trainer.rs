@@ -0,0 +1,148 @@
+/*
+  Speed trainer, which steps the tempo from a start BPM to a target BPM as loops are played.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedTrainerSettings {
+    pub start_bpm: f64,
+    pub target_bpm: f64,
+    /// how much the BPM changes at each step
+    pub step_bpm: f64,
+    /// how many loops are played at each tempo before stepping
+    pub loops_per_step: u32,
+    /// once the target is reached, go back to the start BPM instead of staying at the target
+    pub wrap_back: bool,
+}
+
+impl Default for SpeedTrainerSettings {
+    fn default() -> Self {
+        Self {
+            start_bpm: 80.,
+            target_bpm: 120.,
+            step_bpm: 5.,
+            loops_per_step: 4,
+            wrap_back: false,
+        }
+    }
+}
+
+/// SpeedTrainer advances the tempo every few loops, regardless of score (unlike gold mode).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpeedTrainer {
+    pub settings: SpeedTrainerSettings,
+    pub enabled: bool,
+    loops_at_current_step: u32,
+}
+
+impl SpeedTrainer {
+    /// enables the trainer, returning the BPM to start at
+    pub fn start(&mut self) -> f64 {
+        self.enabled = true;
+        self.loops_at_current_step = 0;
+        self.settings.start_bpm
+    }
+
+    pub fn stop(&mut self) {
+        self.enabled = false;
+    }
+
+    pub fn loops_at_current_step(&self) -> u32 {
+        self.loops_at_current_step
+    }
+
+    /// should be called each time a loop is completed. returns the new BPM, if it should change.
+    pub fn on_loop_completed(&mut self, current_bpm: f64) -> Option<f64> {
+        if !self.enabled {
+            return None;
+        }
+
+        self.loops_at_current_step += 1;
+        if self.loops_at_current_step < self.settings.loops_per_step {
+            return None;
+        }
+        self.loops_at_current_step = 0;
+
+        let SpeedTrainerSettings {
+            start_bpm,
+            target_bpm,
+            step_bpm,
+            wrap_back,
+            ..
+        } = self.settings;
+        // the trainer can also ramp down, from a fast start to a slow target
+        let direction = if target_bpm >= start_bpm { 1. } else { -1. };
+        let remaining = (target_bpm - current_bpm) * direction;
+        if remaining <= 0. {
+            return if wrap_back { Some(start_bpm) } else { None };
+        }
+
+        Some(current_bpm + step_bpm.abs().min(remaining) * direction)
+    }
+
+    /// how far from the start BPM to the target BPM the given BPM is, from 0 to 1
+    pub fn progress(&self, current_bpm: f64) -> f64 {
+        let range = self.settings.target_bpm - self.settings.start_bpm;
+        if range == 0. {
+            return 1.;
+        }
+        ((current_bpm - self.settings.start_bpm) / range).clamp(0., 1.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::speed_trainer::{SpeedTrainer, SpeedTrainerSettings};
+
+    fn trainer(wrap_back: bool) -> SpeedTrainer {
+        SpeedTrainer {
+            settings: SpeedTrainerSettings {
+                start_bpm: 100.,
+                target_bpm: 110.,
+                step_bpm: 4.,
+                loops_per_step: 2,
+                wrap_back,
+            },
+            ..SpeedTrainer::default()
+        }
+    }
+
+    #[test]
+    fn it_steps_towards_the_target_every_few_loops() {
+        let mut trainer = trainer(false);
+        assert_eq!(trainer.on_loop_completed(100.), None);
+
+        let mut bpm = trainer.start();
+        assert_eq!(bpm, 100.);
+
+        let mut bpms = vec![];
+        for _ in 0..8 {
+            if let Some(new_bpm) = trainer.on_loop_completed(bpm) {
+                bpm = new_bpm;
+            }
+            bpms.push(bpm);
+        }
+        // the last step is shortened so it lands on the target, then stays there
+        assert_eq!(bpms, vec![100., 104., 104., 108., 108., 110., 110., 110.]);
+        assert_eq!(trainer.progress(bpm), 1.);
+        assert_eq!(trainer.progress(105.), 0.5);
+    }
+
+    #[test]
+    fn it_wraps_back_to_the_start_after_reaching_the_target() {
+        let mut trainer = trainer(true);
+        trainer.start();
+        assert_eq!(trainer.on_loop_completed(110.), None);
+        assert_eq!(trainer.on_loop_completed(110.), Some(100.));
+    }
+
+    #[test]
+    fn it_ramps_down_when_the_target_is_slower() {
+        let mut trainer = trainer(false);
+        trainer.settings.start_bpm = 120.;
+        trainer.settings.loops_per_step = 1;
+        trainer.start();
+        assert_eq!(trainer.on_loop_completed(120.), Some(116.));
+        assert_eq!(trainer.on_loop_completed(112.), Some(110.));
+        assert_eq!(trainer.progress(115.), 0.5);
+    }
+}
@@ -1,13 +0,0 @@
-/*
- Helper functions for time.
-
- Notably, we have several kinds of time: Audio manager, Macroquad, and Rust time.
-*/
-
-// get curent time in milliseconds
-pub fn current_time_millis() -> u128 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis()
-}
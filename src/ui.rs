@@ -4,7 +4,7 @@ Display the UI.
 The UI is built in EGUI.
 */
 use crate::{
-    consts::{UserHit, BEATS_PER_LOOP},
+    consts::UserHit,
     egui_ui::{draw_ui, UIState},
     events::Events,
     score::MISS_MARGIN,
@@ -31,12 +31,16 @@ impl UI {
     }
 }
 
-pub fn get_hits_from_nth_loop(user_hits: &Vec<UserHit>, desired_loop_idx: usize) -> Vec<UserHit> {
+pub fn get_hits_from_nth_loop(
+    user_hits: &Vec<UserHit>,
+    desired_loop_idx: usize,
+    beats_per_loop: f64,
+) -> Vec<UserHit> {
     let last_loop_hits: Vec<UserHit> = user_hits
         .iter()
         .filter(|hit| {
             // include hits from just before start of loop (back to 0 - MISS), since those could be early or on-time hits
-            let loop_num_for_hit = ((hit.clock_tick + MISS_MARGIN) / BEATS_PER_LOOP) as usize;
+            let loop_num_for_hit = ((hit.clock_tick + MISS_MARGIN) / beats_per_loop) as usize;
             loop_num_for_hit == desired_loop_idx
         })
         .map(|hit| hit.clone())
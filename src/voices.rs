@@ -1,14 +1,16 @@
 /*
   Data structures describing the notes to be played on each instrument.
 */
-use std::error::Error;
+use std::{error::Error, io::Read, path::Path};
 
 use macroquad::file::load_file;
 use serde::{Deserialize, Serialize};
 
-use crate::consts::{ALL_INSTRUMENTS, BEATS_PER_LOOP, GRID_COLS, GRID_ROWS};
+use crate::backing_track::BackingTrack;
+use crate::consts::{ALL_INSTRUMENTS, DEFAULT_SUBDIVISION, GRID_ROWS, MAX_GRID_SUBDIVISION};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
 pub enum Instrument {
     ClosedHihat,
     Snare,
@@ -23,23 +25,55 @@ pub enum Instrument {
     Crash,
 }
 
+/// Dynamic is how hard a note is played.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Dynamic {
+    Ghost,
+    #[default]
+    Normal,
+    Accent,
+}
+
+impl Dynamic {
+    /// multiplier applied to the instrument's volume. Accents play the sample as it is, so they can't clip,
+    /// and the other dynamics are quieter.
+    pub fn gain(&self) -> f64 {
+        match self {
+            Dynamic::Ghost => 0.2,
+            Dynamic::Normal => 0.65,
+            Dynamic::Accent => 1.0,
+        }
+    }
+
+    /// cycles Normal -> Accent -> Ghost -> Normal, e.g. for editing in the beat grid
+    pub fn next(&self) -> Self {
+        match self {
+            Dynamic::Normal => Dynamic::Accent,
+            Dynamic::Accent => Dynamic::Ghost,
+            Dynamic::Ghost => Dynamic::Normal,
+        }
+    }
+}
+
 /// Voice represents the notes to be played on an instrument.
-#[derive(Debug, Clone)]
+/// Each note is its timing, measured in beats (quarter notes) from the start of the loop, and its dynamic.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Voice {
     instrument: Instrument,
-    beat_timings: Vec<f64>,
+    notes: Vec<(f64, Dynamic)>,
 }
 
 impl Voice {
     pub fn new(instrument: Instrument) -> Self {
         Self {
             instrument,
-            beat_timings: vec![],
+            notes: vec![],
         }
     }
 }
 
-/// VoicesOld represents the notes to be played on each instrument.
+/// VoicesFromJSON represents the notes to be played on each instrument, as stored in a v1 loop file.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VoicesFromJSON {
     closed_hihat: Vec<f64>,
@@ -87,129 +121,325 @@ impl Voices {
     pub fn new_from_voices_old_model(vo: &VoicesFromJSON) -> Self {
         let mut data = vec![];
         for ins in ALL_INSTRUMENTS.iter() {
-            let beat_timings = match ins {
-                Instrument::ClosedHihat => vo.closed_hihat.clone(),
-                Instrument::Snare => vo.snare.clone(),
-                Instrument::Kick => vo.kick.clone(),
-                Instrument::OpenHihat => vo.open_hihat.clone(),
-                Instrument::Ride => vo.ride.clone(),
-                Instrument::Crash => vo.crash.clone(),
-                Instrument::Tom1 => vec![],
-                Instrument::Tom2 => vec![],
-                Instrument::Tom3 => vec![],
-                Instrument::PedalHiHat => vec![],
+            let beat_timings: &[f64] = match ins {
+                Instrument::ClosedHihat => &vo.closed_hihat,
+                Instrument::Snare => &vo.snare,
+                Instrument::Kick => &vo.kick,
+                Instrument::OpenHihat => &vo.open_hihat,
+                Instrument::Ride => &vo.ride,
+                Instrument::Crash => &vo.crash,
+                Instrument::Tom1 => &[],
+                Instrument::Tom2 => &[],
+                Instrument::Tom3 => &[],
+                Instrument::PedalHiHat => &[],
             };
             data.push(Voice {
                 instrument: *ins,
-                beat_timings,
+                notes: beat_timings
+                    .iter()
+                    .map(|beat| (*beat, Dynamic::Normal))
+                    .collect(),
             });
         }
         Self { data }
     }
 
+    /// builds Voices from a (possibly partial) list of voices, e.g. as read from a loop file
+    pub fn new_from_voice_list(voice_list: &[Voice]) -> Self {
+        let mut voices = Voices::new();
+        for v in voice_list {
+            let voice = voices.get_voice_mut(&v.instrument);
+            voice.notes.extend(v.notes.iter());
+        }
+        voices
+    }
+
+    pub fn to_voice_list(&self) -> Vec<Voice> {
+        self.data.clone()
+    }
+
     pub fn toggle_beat(&mut self, ins: Instrument, beat: f64) {
-        let ins_vec = self.get_instrument_beats_mut(&ins);
-        if let Some(pos) = ins_vec.iter().position(|x| *x == beat) {
-            ins_vec.remove(pos);
+        let voice = self.get_voice_mut(&ins);
+        if let Some(pos) = voice.notes.iter().position(|(x, _)| is_same_beat(*x, beat)) {
+            voice.notes.remove(pos);
         } else {
-            ins_vec.push(beat);
+            voice.notes.push((beat, Dynamic::Normal));
+        }
+    }
+
+    /// moves the note at `beat` to its next dynamic. Does nothing if there's no such note.
+    pub fn cycle_dynamic(&mut self, ins: Instrument, beat: f64) {
+        let voice = self.get_voice_mut(&ins);
+        if let Some((_, dynamic)) = voice.notes.iter_mut().find(|(x, _)| is_same_beat(*x, beat)) {
+            *dynamic = dynamic.next();
         }
     }
 
-    pub fn get_instrument_beats(self: &Self, ins: &Instrument) -> &Vec<f64> {
+    /// returns each note's timing along with its dynamic
+    pub fn get_instrument_notes(&self, ins: &Instrument) -> Vec<(f64, Dynamic)> {
+        self.get_voice(ins).notes.clone()
+    }
+
+    pub fn get_instrument_beats(self: &Self, ins: &Instrument) -> Vec<f64> {
+        self.get_voice(ins)
+            .notes
+            .iter()
+            .map(|(beat, _)| *beat)
+            .collect()
+    }
+
+    fn get_voice(&self, ins: &Instrument) -> &Voice {
         if let Some(pos) = self.data.iter().position(|x| x.instrument == *ins) {
-            return &self.data[pos].beat_timings;
+            &self.data[pos]
         } else {
             panic!("couldn't find instrument, though ALL_INSTRUMENTS should be present");
         }
     }
 
-    fn get_instrument_beats_mut(self: &mut Self, ins: &Instrument) -> &mut Vec<f64> {
+    fn get_voice_mut(&mut self, ins: &Instrument) -> &mut Voice {
         if let Some(pos) = self.data.iter().position(|x| x.instrument == *ins) {
-            return &mut self.data[pos].beat_timings;
+            &mut self.data[pos]
         } else {
             panic!("couldn't find instrument, though ALL_INSTRUMENTS should be present");
         }
     }
 
-    pub fn get_audio_file_for_instrument(ins: &Instrument) -> &str {
-        // TODO: verify required sound files exist on startup- right now it fails during runtime
-        match ins {
-            Instrument::ClosedHihat => "res/sounds/closed-hihat.wav",
-            Instrument::Snare => "res/sounds/snare.wav",
-            Instrument::Kick => "res/sounds/kick.wav",
-            Instrument::OpenHihat => "res/sounds/open-hihat.wav",
-            Instrument::Ride => "res/sounds/ride.wav",
-            Instrument::Crash => "res/sounds/crash.wav",
-            Instrument::Tom1 => "res/sounds/tom-hi.wav",
-            Instrument::Tom2 => "res/sounds/tom-med.wav",
-            Instrument::Tom3 => "res/sounds/tom-low.wav",
-            Instrument::PedalHiHat => "res/sounds/pedal-hihat.wav",
-            // Instrument::Metronome => "res/sounds/click.wav",
+    /// returns the note in each grid cell (if any), with its exact beat and its dynamic, where each beat is split into `subdivision` cells.
+    /// Notes that fall between cells are shown in the nearest one.
+    pub fn to_enabled_beats(
+        &self,
+        beats_per_loop: f64,
+        subdivision: u32,
+    ) -> Vec<Vec<Option<(f64, Dynamic)>>> {
+        let num_cols = (beats_per_loop * subdivision as f64).ceil() as usize;
+        let mut out = vec![vec![None; num_cols]; GRID_ROWS];
+
+        for (instrument_idx, instrument) in ALL_INSTRUMENTS.iter().enumerate() {
+            for (beat, dynamic) in self.get_instrument_notes(instrument) {
+                let col = (beat * subdivision as f64).round() as usize;
+                if col < num_cols {
+                    out[instrument_idx][col] = Some((beat, dynamic));
+                }
+            }
         }
+
+        out
     }
 
-    pub fn to_voices_old_model(&self) -> VoicesFromJSON {
-        VoicesFromJSON {
-            closed_hihat: self.get_instrument_beats(&Instrument::ClosedHihat).clone(),
-            snare: self.get_instrument_beats(&Instrument::Snare).clone(),
-            kick: self.get_instrument_beats(&Instrument::Kick).clone(),
-            open_hihat: self.get_instrument_beats(&Instrument::OpenHihat).clone(),
-            ride: self.get_instrument_beats(&Instrument::Ride).clone(),
-            crash: self.get_instrument_beats(&Instrument::Crash).clone(),
+    /// returns the coarsest multiple of `subdivision` that puts every note on a grid cell,
+    /// so loops with e.g. 32nd notes or displaced notes get split into sub-cells.
+    /// Falls back to the finest grid allowed if no such multiple exists.
+    pub fn grid_subdivision(&self, subdivision: u32) -> u32 {
+        let fits = |sub: u32| {
+            self.data.iter().all(|voice| {
+                voice.notes.iter().all(|(beat, _)| {
+                    let steps = beat * sub as f64;
+                    is_same_beat(steps, steps.round())
+                })
+            })
+        };
+
+        let mut sub = subdivision.max(1);
+        while sub + subdivision <= MAX_GRID_SUBDIVISION && !fits(sub) {
+            sub += subdivision;
         }
+        sub
     }
 
-    pub fn to_enabled_beats(&self) -> [[bool; GRID_COLS]; GRID_ROWS] {
-        let mut out = [[false; GRID_COLS]; GRID_ROWS];
+    /// keeps the notes from `start_beat` up to (but not including) `end_beat`, moving them so `start_beat` is beat 0
+    pub fn crop(&self, start_beat: f64, end_beat: f64) -> Voices {
+        let data = self
+            .data
+            .iter()
+            .map(|voice| Voice {
+                instrument: voice.instrument,
+                notes: voice
+                    .notes
+                    .iter()
+                    .filter(|(beat, _)| *beat >= start_beat && *beat < end_beat)
+                    .map(|(beat, dynamic)| (beat - start_beat, *dynamic))
+                    .collect(),
+            })
+            .collect();
+        Self { data }
+    }
 
-        for (instrument_idx, instrument) in ALL_INSTRUMENTS.iter().enumerate() {
-            for beat in self.get_instrument_beats(instrument) {
-                for val in 0..(BEATS_PER_LOOP as usize) {
-                    if *beat == (val as f64) {
-                        out[instrument_idx][val] = true;
-                    }
-                }
+    /// multiplies every note timing by `factor`, e.g. to convert between grid steps and beats
+    fn scale_timings(&mut self, factor: f64) {
+        for voice in self.data.iter_mut() {
+            for (beat, _) in voice.notes.iter_mut() {
+                *beat *= factor;
             }
         }
+    }
+}
 
-        out
+/// beats are compared with a small tolerance, since grid positions like triplets (1/3) aren't exact in floating point
+fn is_same_beat(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-6
+}
+
+pub const LOOP_FILE_VERSION: u32 = 2;
+
+/// v1 loops were played with one grid step per clock tick, at `bpm * 2` ticks per minute.
+/// i.e. each step was an eighth note, and a loop was 16 steps long.
+const LEGACY_STEPS_PER_BEAT: u32 = 2;
+const LEGACY_STEPS_PER_LOOP: f64 = 16.;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TimeSignature {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self {
+            numerator: 4,
+            denominator: 4,
+        }
+    }
+}
+
+impl TimeSignature {
+    /// length of a bar, in beats (quarter notes). e.g. 4/4 = 4 beats, 6/8 = 3 beats.
+    pub fn beats_per_bar(&self) -> f64 {
+        self.numerator as f64 * 4. / self.denominator as f64
+    }
+
+    /// length of the pulse that's counted (and clicked by the metronome), in beats (quarter notes).
+    /// compound meters (6/8, 9/8, 12/8) are counted in dotted quarters, others in the denominator's note value.
+    pub fn pulse_beats(&self) -> f64 {
+        let note_value = 4. / self.denominator as f64;
+        if self.is_compound() {
+            note_value * 3.
+        } else {
+            note_value
+        }
+    }
+
+    fn is_compound(&self) -> bool {
+        self.denominator >= 8 && self.numerator > 3 && self.numerator % 3 == 0
     }
 }
 
 /// Loop is the full information required to play a loop. It can be read/written to a file.
+///
+/// Files are versioned. Older versions are upgraded in memory when read, and are always written as the latest version.
+///
+/// Timings (`length_beats` and each voice's notes) are measured in beats, where a beat is a quarter note.
+/// `bpm` is quarter notes per minute, and `subdivision` is the number of grid steps per beat (e.g. 4 for 16ths, 3 for triplets).
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Loop {
+    pub version: u32,
+    pub name: String,
     pub bpm: usize,
-    pub voices: VoicesFromJSON,
+    pub length_beats: f64,
+    pub time_signature: TimeSignature,
+    pub subdivision: u32,
+    pub voices: Vec<Voice>,
+    /// audio played along with the loop, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backing_track: Option<BackingTrack>,
+}
+
+/// LoopV1 is the original loop file format, which had no version field and a fixed set of instruments.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LoopV1 {
+    bpm: usize,
+    voices: VoicesFromJSON,
 }
 
 impl Loop {
+    pub fn new(name: &str, bpm: usize, length_beats: f64, voices: &Voices) -> Self {
+        Self {
+            version: LOOP_FILE_VERSION,
+            name: name.to_string(),
+            bpm,
+            length_beats,
+            time_signature: TimeSignature::default(),
+            subdivision: DEFAULT_SUBDIVISION,
+            voices: voices.to_voice_list(),
+            backing_track: None,
+        }
+    }
+
+    pub fn with_backing_track(mut self, backing_track: Option<BackingTrack>) -> Self {
+        self.backing_track = backing_track;
+        self
+    }
+
+    pub fn with_meter(mut self, time_signature: TimeSignature, subdivision: u32) -> Self {
+        self.time_signature = time_signature;
+        self.subdivision = subdivision;
+        self
+    }
+
     pub async fn new_from_file_async(path: &str) -> Result<Self, Box<dyn Error>> {
         log::info!("Loop::new_from_file .. {}", path);
         let f = load_file(path).await?;
-        let out: Self = serde_json::from_reader(&*f)?;
-        Ok(out)
+        Loop::new_from_reader(&*f, path)
     }
 
     pub fn new_from_file(path: &str) -> Result<Self, Box<dyn Error>> {
         // load file at path
         let f = std::fs::File::open(path)?;
-        let out: Self = serde_json::from_reader(f)?;
+        Loop::new_from_reader(f, path)
+    }
+
+    /// reads a loop of any supported version, upgrading it to the latest version.
+    /// the file name (without extension) is used as the name if the loop doesn't have one.
+    fn new_from_reader(reader: impl Read, path: &str) -> Result<Self, Box<dyn Error>> {
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        // v1 files predate the version field
+        let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(1);
+
+        let mut out = match version {
+            1 => {
+                let v1: LoopV1 = serde_json::from_value(value)?;
+                let mut voices = Voices::new_from_voices_old_model(&v1.voices);
+                voices.scale_timings(1. / LEGACY_STEPS_PER_BEAT as f64);
+                Loop::new(
+                    "",
+                    v1.bpm,
+                    LEGACY_STEPS_PER_LOOP / LEGACY_STEPS_PER_BEAT as f64,
+                    &voices,
+                )
+                .with_meter(TimeSignature::default(), LEGACY_STEPS_PER_BEAT)
+            }
+            2 => serde_json::from_value(value)?,
+            v => return Err(format!("unsupported loop file version: {}", v).into()),
+        };
+
+        if out.name.is_empty() {
+            out.name = Path::new(path)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string();
+        }
+
         Ok(out)
     }
+
+    pub fn voices(&self) -> Voices {
+        Voices::new_from_voice_list(&self.voices)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::voices::{Instrument, Loop, Voices};
+    use crate::{
+        consts::{ALL_INSTRUMENTS, MAX_GRID_SUBDIVISION},
+        voices::{Dynamic, Instrument, Loop, TimeSignature, Voices, LOOP_FILE_VERSION},
+    };
 
     #[test]
     fn it_can_load_a_loop_from_file() {
         let result = Loop::new_from_file("res/loops/samba.json");
         let loop_data = result.unwrap();
         assert_eq!(loop_data.bpm, 120);
-        let voices = Voices::new_from_voices_old_model(&loop_data.voices);
+        let voices = loop_data.voices();
         assert_eq!(
             voices.get_instrument_beats(&Instrument::ClosedHihat).len(),
             12
@@ -220,4 +450,172 @@ mod tests {
         assert_eq!(voices.get_instrument_beats(&Instrument::OpenHihat).len(), 4);
         assert_eq!(voices.get_instrument_beats(&Instrument::Ride).len(), 0);
     }
+
+    #[test]
+    fn it_upgrades_a_v1_loop() {
+        let loop_data = Loop::new_from_file("res/loops/samba.json").unwrap();
+        assert_eq!(loop_data.version, LOOP_FILE_VERSION);
+        assert_eq!(loop_data.name, "samba");
+        assert_eq!(loop_data.length_beats, 8.);
+        assert_eq!(loop_data.time_signature.numerator, 4);
+        assert_eq!(loop_data.time_signature.denominator, 4);
+        assert_eq!(loop_data.subdivision, 2);
+
+        // grid steps are converted to beats
+        let voices = loop_data.voices();
+        assert_eq!(
+            voices.get_instrument_beats(&Instrument::OpenHihat),
+            vec![1.0, 3.0, 5.0, 7.0]
+        );
+    }
+
+    #[test]
+    fn it_round_trips_a_loop() {
+        let mut voices = Voices::new();
+        voices.toggle_beat(Instrument::Tom1, 3.0);
+        voices.toggle_beat(Instrument::PedalHiHat, 1.0);
+        let time_signature = TimeSignature {
+            numerator: 6,
+            denominator: 8,
+        };
+        let loop_data = Loop::new("toms", 90, 32., &voices).with_meter(time_signature, 2);
+
+        let json = serde_json::to_string(&loop_data).unwrap();
+        let result = Loop::new_from_reader(json.as_bytes(), "ignored.json").unwrap();
+        assert_eq!(result.name, "toms");
+        assert_eq!(result.bpm, 90);
+        assert_eq!(result.length_beats, 32.);
+        assert_eq!(result.time_signature, time_signature);
+        assert_eq!(result.subdivision, 2);
+        let voices = result.voices();
+        assert_eq!(voices.get_instrument_beats(&Instrument::Tom1), vec![3.0]);
+        assert_eq!(
+            voices.get_instrument_beats(&Instrument::PedalHiHat),
+            vec![1.0]
+        );
+    }
+
+    #[test]
+    fn it_computes_the_pulse_of_a_time_signature() {
+        let ts = |numerator, denominator| TimeSignature {
+            numerator,
+            denominator,
+        };
+        assert_eq!(ts(4, 4).pulse_beats(), 1.);
+        assert_eq!(ts(4, 4).beats_per_bar(), 4.);
+        assert_eq!(ts(3, 4).beats_per_bar(), 3.);
+        assert_eq!(ts(7, 8).pulse_beats(), 0.5);
+        assert_eq!(ts(6, 8).pulse_beats(), 1.5);
+        assert_eq!(ts(6, 8).beats_per_bar(), 3.);
+        assert_eq!(ts(12, 8).pulse_beats(), 1.5);
+        assert_eq!(ts(12, 8).beats_per_bar(), 6.);
+    }
+
+    #[test]
+    fn it_enables_grid_cells_for_triplets() {
+        let mut voices = Voices::new();
+        voices.toggle_beat(Instrument::Snare, 1. / 3.);
+        voices.toggle_beat(Instrument::Snare, 1.);
+        let enabled = voices.to_enabled_beats(2., 3);
+        let snare_row = &enabled[ALL_INSTRUMENTS
+            .iter()
+            .position(|ins| *ins == Instrument::Snare)
+            .unwrap()];
+        let note = |beat| Some((beat, Dynamic::Normal));
+        assert_eq!(
+            snare_row,
+            &vec![None, note(1. / 3.), None, note(1.), None, None]
+        );
+
+        // toggling the same triplet again removes it
+        voices.toggle_beat(Instrument::Snare, 1. / 3.);
+        assert_eq!(voices.get_instrument_beats(&Instrument::Snare), vec![1.]);
+    }
+
+    #[test]
+    fn it_splits_the_grid_for_off_grid_notes() {
+        let mut voices = Voices::new();
+        voices.toggle_beat(Instrument::ClosedHihat, 0.5);
+        assert_eq!(voices.grid_subdivision(4), 4);
+
+        // a 32nd note needs twice as many cells
+        voices.toggle_beat(Instrument::ClosedHihat, 0.125);
+        assert_eq!(voices.grid_subdivision(4), 8);
+
+        let enabled = voices.to_enabled_beats(1., 8);
+        let hihat_row = &enabled[ALL_INSTRUMENTS
+            .iter()
+            .position(|ins| *ins == Instrument::ClosedHihat)
+            .unwrap()];
+        let note = |beat| Some((beat, Dynamic::Normal));
+        assert_eq!(
+            hihat_row,
+            &vec![None, note(0.125), None, None, note(0.5), None, None, None]
+        );
+
+        // a note that can't be placed exactly uses the finest grid, snapped to the nearest cell
+        voices.toggle_beat(Instrument::Kick, 0.01);
+        assert_eq!(voices.grid_subdivision(4), MAX_GRID_SUBDIVISION);
+        let enabled = voices.to_enabled_beats(1., 4);
+        let kick_row = &enabled[ALL_INSTRUMENTS
+            .iter()
+            .position(|ins| *ins == Instrument::Kick)
+            .unwrap()];
+        // the cell keeps the note's exact beat, so clicking it removes that note
+        assert_eq!(kick_row[0], Some((0.01, Dynamic::Normal)));
+        voices.toggle_beat(Instrument::Kick, 0.01);
+        assert!(voices.get_instrument_beats(&Instrument::Kick).is_empty());
+    }
+
+    #[test]
+    fn it_tracks_the_dynamic_of_each_note() {
+        let mut voices = Voices::new();
+        voices.toggle_beat(Instrument::Snare, 1.);
+        voices.toggle_beat(Instrument::Snare, 1.75);
+        voices.cycle_dynamic(Instrument::Snare, 1.);
+        voices.cycle_dynamic(Instrument::Snare, 1.75);
+        voices.cycle_dynamic(Instrument::Snare, 1.75);
+        assert_eq!(
+            voices.get_instrument_notes(&Instrument::Snare),
+            vec![(1., Dynamic::Accent), (1.75, Dynamic::Ghost)]
+        );
+
+        // dynamics are saved in the loop file
+        let loop_data = Loop::new("funk", 100, 4., &voices);
+        let json = serde_json::to_string(&loop_data).unwrap();
+        let voices = Loop::new_from_reader(json.as_bytes(), "ignored.json")
+            .unwrap()
+            .voices();
+        assert_eq!(
+            voices.get_instrument_notes(&Instrument::Snare),
+            vec![(1., Dynamic::Accent), (1.75, Dynamic::Ghost)]
+        );
+
+        // removing a note forgets its dynamic
+        let mut voices = voices;
+        voices.toggle_beat(Instrument::Snare, 1.);
+        voices.toggle_beat(Instrument::Snare, 1.);
+        assert_eq!(
+            voices.get_instrument_notes(&Instrument::Snare),
+            vec![(1.75, Dynamic::Ghost), (1., Dynamic::Normal)]
+        );
+
+        // cropping, e.g. to a practice region, moves each note along with its dynamic
+        assert_eq!(
+            voices
+                .crop(1.5, 2.)
+                .get_instrument_notes(&Instrument::Snare),
+            vec![(0.25, Dynamic::Ghost)]
+        );
+
+        // notes without a dynamic are unaffected
+        voices.cycle_dynamic(Instrument::Kick, 0.);
+        assert!(voices.get_instrument_notes(&Instrument::Kick).is_empty());
+    }
+
+    #[test]
+    fn it_rejects_unknown_loop_versions() {
+        let json = r#"{"version": 99}"#;
+        assert!(Loop::new_from_reader(json.as_bytes(), "future.json").is_err());
+    }
 }